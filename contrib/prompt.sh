@@ -6,6 +6,13 @@
 #   Source this file in your .bashrc or .zshrc:
 #   source /path/to/prompt.sh
 #
+# For hooks that keep an existing PROMPT_COMMAND, precmd and prompt colors, use
+# the integration built into promptpath instead:
+#   eval "$(promptpath init bash)"   # or zsh; see promptpath --help for others
+#
+# Add --daemon to keep the config loaded between prompts:
+#   eval "$(promptpath init bash --daemon)"
+#
 # Update project mappings in ~/.config/promptpath/config.toml
 #
 [ ${ZSH_VERSION} ] && precmd() { prompt; }
@@ -14,9 +21,15 @@
 prompt() {
   pwddisplay=$PWD
   if command -v promptpath &> /dev/null; then
-    pwddisplay=$(promptpath)
+    # zsh expands the directory as part of PROMPT, bash expands $pwddisplay without looking inside
+    if [ ${ZSH_VERSION} ]; then
+      pwddisplay=$(promptpath --shell zsh)
+    else
+      pwddisplay=$(promptpath --shell plain)
+    fi
   fi
   if [ ${ZSH_VERSION} ]; then
+    setopt prompt_subst
     PROMPT="%F{$GREEN}$pwddisplay%f%F{$BLUE}~>%f "
   elif [ ${BASH_VERSION} ]; then
     PS1='\[\e[32m\]$pwddisplay\[\e[m\]\[\e[32m\]~>\[\e[m\] '
@@ -0,0 +1,280 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use crate::config::InvalidUtf8Config;
+use crate::encoding;
+
+// Shortens every component of subpath except the last keep_full to its first length characters.
+// Hidden directories keep their leading dot in addition to the kept characters. Names that aren't
+// valid UTF-8 are left in full, as in unique.
+pub fn fish(
+    subpath: &Path,
+    keep_full: usize,
+    length: usize,
+    invalid_utf8: &InvalidUtf8Config,
+) -> String {
+    let components: Vec<&OsStr> = subpath.iter().collect();
+    let abbreviated = components.len().saturating_sub(keep_full);
+
+    let mut result = String::with_capacity(subpath.as_os_str().len());
+    for (i, component) in components.iter().enumerate() {
+        if i > 0 {
+            result.push('/');
+        }
+        match component.to_str() {
+            Some(name) if i < abbreviated => result.push_str(shorten(name, length)),
+            _ => result.push_str(&encoding::render(component, invalid_utf8)),
+        }
+    }
+    result
+}
+
+// Shortens every component of subpath except the last keep_full to the shortest prefix of at
+// least length characters that no sibling directory on disk also starts with, like zsh's
+// shrink-path. base is the directory containing the first component. Directories with more than
+// max_entries entries aren't scanned and their components are left in full, as are names that
+// aren't valid UTF-8 since cutting them could split an escape sequence.
+pub fn unique(
+    base: &Path,
+    subpath: &Path,
+    keep_full: usize,
+    length: usize,
+    max_entries: usize,
+    invalid_utf8: &InvalidUtf8Config,
+) -> String {
+    let components: Vec<&OsStr> = subpath.iter().collect();
+    let abbreviated = components.len().saturating_sub(keep_full);
+
+    let mut dir = base.to_path_buf();
+    let mut result = String::with_capacity(subpath.as_os_str().len());
+    for (i, component) in components.iter().enumerate() {
+        if i > 0 {
+            result.push('/');
+        }
+        let name = encoding::render(component, invalid_utf8);
+        if i < abbreviated && component.to_str().is_some() {
+            let siblings = sibling_dirs(&dir, component, max_entries, invalid_utf8);
+            result.push_str(unique_prefix(&name, siblings, length));
+        } else {
+            result.push_str(&name);
+        }
+        dir.push(component);
+    }
+    result
+}
+
+// Returns the shortest prefix of component that doesn't start the name of any of its siblings, or
+// the whole component if it can't be made unique or the siblings couldn't be listed
+fn unique_prefix(component: &str, siblings: Option<Vec<String>>, length: usize) -> &str {
+    let siblings = match siblings {
+        Some(siblings) => siblings,
+        None => return component,
+    };
+
+    let skip = usize::from(component.starts_with('.'));
+    let min_len = skip + length.max(1);
+    for (end, _) in component.char_indices().skip(min_len) {
+        let prefix = &component[..end];
+        if !siblings.iter().any(|sibling| sibling.starts_with(prefix)) {
+            return prefix;
+        }
+    }
+    component
+}
+
+// Lists the displayed names of the directories in dir other than component, or None if dir can't
+// be read or has more than max_entries entries
+fn sibling_dirs(
+    dir: &Path,
+    component: &OsStr,
+    max_entries: usize,
+    invalid_utf8: &InvalidUtf8Config,
+) -> Option<Vec<String>> {
+    let mut siblings = Vec::new();
+    for (count, entry) in fs::read_dir(dir).ok()?.flatten().enumerate() {
+        if count >= max_entries {
+            return None;
+        }
+
+        let name = entry.file_name();
+        if name == component {
+            continue;
+        }
+        let is_dir = match entry.file_type() {
+            Ok(file_type) if file_type.is_symlink() => entry.path().is_dir(),
+            Ok(file_type) => file_type.is_dir(),
+            Err(_) => false,
+        };
+        if is_dir {
+            siblings.push(encoding::render(&name, invalid_utf8).into_owned());
+        }
+    }
+    Some(siblings)
+}
+
+// Returns the first length characters of a component, not counting a leading dot
+fn shorten(component: &str, length: usize) -> &str {
+    let skip = usize::from(component.starts_with('.'));
+    match component.char_indices().nth(skip + length.max(1)) {
+        Some((end, _)) => &component[..end],
+        None => component,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::scratch_dir;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn test_fish() {
+        let test_cases = [
+            (
+                "data/archive/2024/customers/exports",
+                1,
+                1,
+                "d/a/2/c/exports",
+            ),
+            (
+                "data/archive/2024/customers/exports",
+                2,
+                1,
+                "d/a/2/customers/exports",
+            ),
+            (
+                "data/archive/2024/customers/exports",
+                1,
+                3,
+                "dat/arc/202/cus/exports",
+            ),
+            ("data/archive", 5, 1, "data/archive"),
+            (".config/nvim/lua", 1, 1, ".c/n/lua"),
+            (".config/nvim/lua", 1, 2, ".co/nv/lua"),
+            ("über/straße", 1, 1, "ü/straße"),
+            ("a/b", 0, 1, "a/b"),
+            ("exports", 1, 1, "exports"),
+        ];
+
+        let invalid_utf8 = InvalidUtf8Config::default();
+        for (input, keep_full, length, expected) in test_cases {
+            assert_eq!(
+                fish(Path::new(input), keep_full, length, &invalid_utf8),
+                expected,
+                "Failed test: fish({:?}, {}, {})",
+                input,
+                keep_full,
+                length
+            );
+        }
+
+        // Names that aren't valid UTF-8 are shown escaped rather than cut
+        assert_eq!(
+            fish(
+                Path::new(OsStr::from_bytes(b"data/\xffcd/exports")),
+                1,
+                1,
+                &invalid_utf8
+            ),
+            r"d/\xffcd/exports"
+        );
+    }
+
+    #[test]
+    fn test_unique() {
+        let root = scratch_dir("abbrev-unique");
+        for dir in [
+            "github.com/tyler-smith/promptpath/src",
+            "github.com/tokio-rs/tokio",
+            "github.com/torvalds",
+            "gitlab.com",
+            ".config/nvim",
+            ".cache",
+            "caf\u{e9}",
+            "foo/bar",
+            "foobar",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("github.com/tyler-smith-notes.txt"), "").unwrap();
+        fs::create_dir_all(root.join(OsStr::from_bytes(b"caf\xe9/menu"))).unwrap();
+
+        let test_cases = [
+            (
+                "github.com/tyler-smith/promptpath/src",
+                1,
+                1,
+                100,
+                "gith/ty/p/src",
+            ),
+            ("github.com/tokio-rs/tokio", 1, 1, 100, "gith/tok/tokio"),
+            ("github.com/tokio-rs/tokio", 1, 4, 100, "gith/toki/tokio"),
+            (
+                "github.com/tokio-rs/tokio",
+                2,
+                1,
+                100,
+                "gith/tokio-rs/tokio",
+            ),
+            (".config/nvim", 1, 1, 100, ".co/nvim"),
+            ("foo/bar", 1, 1, 100, "foo/bar"),
+            ("caf\u{e9}/menu", 1, 1, 100, "caf\u{e9}/menu"),
+            (
+                "github.com/tokio-rs/tokio",
+                1,
+                1,
+                3,
+                "github.com/tokio-rs/tokio",
+            ),
+        ];
+
+        let invalid_utf8 = InvalidUtf8Config::default();
+        for (input, keep_full, length, max_entries, expected) in test_cases {
+            assert_eq!(
+                unique(
+                    &root,
+                    Path::new(input),
+                    keep_full,
+                    length,
+                    max_entries,
+                    &invalid_utf8
+                ),
+                expected,
+                "Failed test: unique({:?}, {}, {}, {})",
+                input,
+                keep_full,
+                length,
+                max_entries
+            );
+        }
+
+        // Unreadable directories are left in full
+        assert_eq!(
+            unique(
+                &root.join("absent"),
+                Path::new("a/b"),
+                1,
+                1,
+                100,
+                &invalid_utf8
+            ),
+            "a/b"
+        );
+
+        // Names that aren't valid UTF-8 are walked by their bytes and shown escaped
+        assert_eq!(
+            unique(
+                &root,
+                Path::new(OsStr::from_bytes(b"caf\xe9/menu")),
+                1,
+                1,
+                100,
+                &invalid_utf8
+            ),
+            r"caf\xe9/menu"
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use std::ffi::OsStr;
+use std::io::{self, BufRead, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::reload::LiveContext;
+use crate::{cli, encoding, get_branch_segment, resolve_nickname, Rule};
+
+// Reads delimiter separated paths from input and writes their nicknames to output in the same
+// order. Plain output uses the same delimiter as the input, JSON output is always one object per
+// line. Empty input records produce empty output records so the two stay aligned. The config is
+// reloaded between records if it changes, for consumers that keep the input open.
+pub fn run(
+    live: &mut LiveContext,
+    cwd: &Path,
+    input: impl BufRead,
+    mut output: impl Write,
+    delimiter: u8,
+    json: bool,
+) -> io::Result<()> {
+    for record in input.split(delimiter) {
+        let record = record?;
+        let record = match delimiter {
+            b'\n' => record.strip_suffix(b"\r").unwrap_or(&record),
+            _ => &record,
+        };
+
+        if record.is_empty() {
+            if json {
+                writeln!(output)?;
+            } else {
+                output.write_all(&[delimiter])?;
+            }
+            continue;
+        }
+
+        let ctx = live.get();
+        let input_path = Path::new(OsStr::from_bytes(record));
+        let path = cli::normalize_path(cwd, input_path);
+        let nickname = resolve_nickname(ctx, &path);
+        let display = format!("{}{}", nickname, get_branch_segment(ctx, &path, None));
+
+        if json {
+            let alias = match nickname.rule {
+                Rule::None => None,
+                _ if nickname.alias.is_empty() => None,
+                _ => Some(nickname.alias.as_str()),
+            };
+            output.write_all(b"{\"path\":")?;
+            write_json_string(
+                &mut output,
+                &encoding::render(input_path.as_os_str(), &ctx.invalid_utf8),
+            )?;
+            output.write_all(b",\"nickname\":")?;
+            write_json_string(&mut output, &display)?;
+            output.write_all(b",\"alias\":")?;
+            match alias {
+                Some(alias) => write_json_string(&mut output, alias)?,
+                None => output.write_all(b"null")?,
+            }
+            output.write_all(b",\"rule\":")?;
+            write_json_string(&mut output, nickname.rule.name())?;
+            output.write_all(b"}\n")?;
+        } else {
+            output.write_all(display.as_bytes())?;
+            output.write_all(&[delimiter])?;
+        }
+    }
+    output.flush()
+}
+
+// Writes s as a quoted JSON string
+fn write_json_string(output: &mut impl Write, s: &str) -> io::Result<()> {
+    output.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => output.write_all(b"\\\"")?,
+            '\\' => output.write_all(b"\\\\")?,
+            '\n' => output.write_all(b"\\n")?,
+            '\r' => output.write_all(b"\\r")?,
+            '\t' => output.write_all(b"\\t")?,
+            c if u32::from(c) < 0x20 => write!(output, "\\u{:04x}", u32::from(c))?,
+            c => write!(output, "{}", c)?,
+        }
+    }
+    output.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reload::tests::live_context;
+    use crate::tests::setup_test_context;
+
+    fn run_batch(input: &[u8], delimiter: u8, json: bool) -> String {
+        let mut live = live_context(setup_test_context());
+        let mut output = Vec::new();
+        run(
+            &mut live,
+            Path::new("/Users/tcrypt/code"),
+            input,
+            &mut output,
+            delimiter,
+            json,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_plain() {
+        let input = b"/Users/tcrypt/data\n\ngithub.com/tyler-smith/promptpath/src\r\n/usr/bin";
+        assert_eq!(
+            run_batch(input, b'\n', false),
+            "~/data\n\npromptpath/src\n/usr/bin\n"
+        );
+
+        let input = b"/Users/tcrypt/data\0/with\nnewline\0";
+        assert_eq!(run_batch(input, b'\0', false), "~/data\0/with\nnewline\0");
+    }
+
+    #[test]
+    fn test_json() {
+        let input =
+            b"/Users/tcrypt\ngithub.com/tyler-smith/promptpath\n/Users/tcrypt/code/x\n/tmp/\"q\"\n";
+        let expected = concat!(
+            r#"{"path":"/Users/tcrypt","nickname":"~","alias":"~","rule":"home"}"#,
+            "\n",
+            r#"{"path":"github.com/tyler-smith/promptpath","nickname":"promptpath","alias":"promptpath","rule":"project"}"#,
+            "\n",
+            r#"{"path":"/Users/tcrypt/code/x","nickname":"x","alias":null,"rule":"code"}"#,
+            "\n",
+            r#"{"path":"/tmp/\"q\"","nickname":"/tmp/\"q\"","alias":null,"rule":"none"}"#,
+            "\n",
+        );
+        assert_eq!(run_batch(input, b'\n', true), expected);
+    }
+
+    #[test]
+    fn test_write_json_string() {
+        let mut output = Vec::new();
+        write_json_string(&mut output, "a\"b\\c\td\u{1b}é").unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), r#""a\"b\\c\td\u001bé""#);
+    }
+}
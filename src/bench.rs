@@ -0,0 +1,138 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::hint::black_box;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{resolve_nickname, AppContext};
+
+// Numbers of generated project mappings each round is timed with
+const MAPPING_COUNTS: [usize; 4] = [100, 1_000, 10_000, 100_000];
+
+// How long each measurement is repeated for, although a load is always timed at least once
+const ROUND_TIME: Duration = Duration::from_millis(500);
+
+// Times what a prompt costs with growing numbers of generated project mappings, printing a line
+// for each. Loading the context is timed with the config already in the cache, and without, when
+// it's parsed and expanded and the cache is written again. The lookups are then timed on the
+// loaded context. The mappings are under a directory in home that doesn't need to exist, and the
+// config and cache are written to a temporary directory that's removed afterwards, so the user's
+// own are left alone.
+pub fn run() -> io::Result<()> {
+    let dir = env::temp_dir().join(format!("promptpath-bench-{}", process::id()));
+    fs::create_dir_all(&dir)?;
+    let result = run_in(&dir);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn run_in(dir: &Path) -> io::Result<()> {
+    let config_path = dir.join("config.toml");
+    let cache_dir = dir.join("cache");
+    // Nothing else runs yet, so nothing else is reading the environment
+    env::set_var("XDG_CACHE_HOME", &cache_dir);
+
+    println!(
+        "{:>10}  {:>10}  {:>10}  {:>12}  {:>10}  {:>10}",
+        "mappings", "uncached", "cached", "project root", "inside", "unmapped"
+    );
+    for count in MAPPING_COUNTS {
+        write_config(&config_path, count)?;
+
+        let remove_cache = || {
+            let _ = fs::remove_dir_all(&cache_dir);
+        };
+        let uncached = time_load(remove_cache, || AppContext::load(Some(&config_path)));
+        let cached = time_load(|| {}, || AppContext::load(Some(&config_path)));
+
+        // The last mapping, a path deep inside it, and a path next to the mappings that doesn't
+        // match any of them
+        let (ctx, _) = AppContext::load(Some(&config_path));
+        let base = ctx
+            .home
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("/"))
+            .join("promptpath-bench");
+        let last = base.join(mapping_path(count - 1));
+        let inside = last.join("src/bin/promptpath/deeply/nested");
+        let unmapped = base.join("unmapped/src");
+        println!(
+            "{:>10}  {:>10}  {:>10}  {:>12}  {:>10}  {:>10}",
+            ctx.project_mappings.len(),
+            format_duration(uncached),
+            format_duration(cached),
+            format_duration(time_lookup(&ctx, &last)),
+            format_duration(time_lookup(&ctx, &inside)),
+            format_duration(time_lookup(&ctx, &unmapped)),
+        );
+    }
+    Ok(())
+}
+
+// Writes a config with count project mappings and nothing else, dated so the cache accepts it
+fn write_config(path: &Path, count: usize) -> io::Result<()> {
+    let mut config = String::from("code_roots = []\nprojects = [\n");
+    for i in 0..count {
+        let _ = writeln!(
+            config,
+            "    {{ path = \"~/promptpath-bench/{}\", alias = \"project-{}\" }},",
+            mapping_path(i),
+            i
+        );
+    }
+    config.push_str("]\n");
+    fs::write(path, config)?;
+
+    let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+    fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(an_hour_ago)
+}
+
+// Spreads the mappings over a hundred organizations, the way clones under ~/code/github.com are
+fn mapping_path(i: usize) -> String {
+    format!("org-{}/repo-{}", i % 100, i)
+}
+
+// Returns the average time load takes, including dropping what it returns as a prompt does
+// before it exits. Each run is set up first with setup, which isn't timed.
+fn time_load<T>(mut setup: impl FnMut(), mut load: impl FnMut() -> T) -> Duration {
+    let mut total = Duration::ZERO;
+    let mut runs = 0;
+    while runs == 0 || total < ROUND_TIME {
+        setup();
+        let start = Instant::now();
+        drop(black_box(load()));
+        total += start.elapsed();
+        runs += 1;
+    }
+    total / runs
+}
+
+// Returns the average time taken to resolve the nickname of path
+fn time_lookup(ctx: &AppContext, path: &Path) -> Duration {
+    let start = Instant::now();
+    let mut lookups = 0;
+    while start.elapsed() < ROUND_TIME {
+        for _ in 0..100 {
+            black_box(resolve_nickname(ctx, black_box(path)));
+        }
+        lookups += 100;
+    }
+    start.elapsed() / lookups
+}
+
+fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    if nanos < 10_000 {
+        format!("{} ns", nanos)
+    } else if nanos < 10_000_000 {
+        format!("{:.1} µs", nanos as f64 / 1e3)
+    } else {
+        format!("{:.1} ms", nanos as f64 / 1e6)
+    }
+}
@@ -0,0 +1,524 @@
+use std::cell::RefCell;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use crate::config::{self, Config, ConfigError, MappingError, MappingKind, ProjectMapping};
+use crate::{passwd, CodeRoot, ExpandedConfig, ExpandedMapping};
+
+// First field of every cache file. Caches written by another version are ignored, since the
+// config they hold may not mean the same thing.
+const MAGIC: &str = concat!("promptpath config cache 2 ", env!("CARGO_PKG_VERSION"));
+
+// A config modified this recently isn't cached. Some filesystems only keep modification times to
+// the second, so an edit straight after the cache was written could leave its key unchanged.
+const MIN_AGE: Duration = Duration::from_secs(2);
+
+// Loads the config at path with its mappings expanded, from the cache in dir if it was written
+// for the file as it is now and with the same home, variables and users. Otherwise the file is
+// parsed and expanded with expand, which must look variables and users up through the lookups it's
+// given, and cached for next time if there's a cache directory. Problems with the cache itself are
+// ignored, since parsing the file always works as well.
+pub fn load(
+    dir: Option<&Path>,
+    path: &Path,
+    home: &Path,
+    expand: impl FnOnce(Config, &Lookups) -> ExpandedConfig,
+) -> Result<ExpandedConfig, ConfigError> {
+    let lookups = Lookups::new();
+    let (Some(dir), Ok(metadata)) = (dir, fs::metadata(path)) else {
+        return Ok(expand(config::load(path)?, &lookups));
+    };
+    let key = Key::new(path, &metadata);
+    let cache_path = dir.join(file_name(path));
+    if let Some(expanded) = fs::read(&cache_path)
+        .ok()
+        .and_then(|cache| decode(&cache, &key, home, &lookups))
+    {
+        return Ok(expanded);
+    }
+
+    let expanded = expand(config::load(path)?, &lookups);
+    let settled = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age >= MIN_AGE);
+    if settled {
+        if let Some(cache) = encode(&expanded, &key, home, &lookups) {
+            let _ = write(dir, &cache_path, &cache);
+        }
+    }
+    Ok(expanded)
+}
+
+// Looks up the environment variables and users' homes that paths in the config use, remembering
+// each answer, since an expanded config only holds while they stay the same
+pub struct Lookups {
+    var: fn(&str) -> Option<OsString>,
+    user_home: fn(&str) -> Option<PathBuf>,
+    vars: RefCell<Vec<(String, Option<OsString>)>>,
+    users: RefCell<Vec<(String, Option<PathBuf>)>>,
+}
+
+impl Lookups {
+    fn new() -> Self {
+        Self::with(
+            |name| env::var_os(name),
+            |name| passwd::by_name(name).map(|user| user.home),
+        )
+    }
+
+    fn with(var: fn(&str) -> Option<OsString>, user_home: fn(&str) -> Option<PathBuf>) -> Self {
+        Self {
+            var,
+            user_home,
+            vars: RefCell::new(Vec::new()),
+            users: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn var(&self, name: &str) -> Option<OsString> {
+        record(&self.vars, name, self.var)
+    }
+
+    pub fn user_home(&self, name: &str) -> Option<PathBuf> {
+        record(&self.users, name, self.user_home)
+    }
+}
+
+// Looks name up, remembering the answer the first time it's asked for
+fn record<T: Clone>(
+    answers: &RefCell<Vec<(String, Option<T>)>>,
+    name: &str,
+    look_up: fn(&str) -> Option<T>,
+) -> Option<T> {
+    let mut answers = answers.borrow_mut();
+    if let Some((_, value)) = answers.iter().find(|(known, _)| known == name) {
+        return value.clone();
+    }
+    let value = look_up(name);
+    answers.push((name.to_string(), value.clone()));
+    value
+}
+
+// Identifies the version of the config file a cache was written from. Saving the file changes its
+// modification time, and replacing it also changes its inode.
+#[derive(Debug, PartialEq)]
+struct Key {
+    path: Vec<u8>,
+    len: u64,
+    modified: (i64, i64),
+    dev: u64,
+    ino: u64,
+}
+
+impl Key {
+    fn new(path: &Path, metadata: &Metadata) -> Self {
+        Self {
+            path: path.as_os_str().as_bytes().to_vec(),
+            len: metadata.len(),
+            modified: (metadata.mtime(), metadata.mtime_nsec()),
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }
+    }
+
+    fn fields(&self) -> [Vec<u8>; 5] {
+        [
+            self.path.clone(),
+            self.len.to_string().into_bytes(),
+            format!("{}.{}", self.modified.0, self.modified.1).into_bytes(),
+            self.dev.to_string().into_bytes(),
+            self.ino.to_string().into_bytes(),
+        ]
+    }
+}
+
+// Names the cache file after a hash of the config's path, so each config file has its own. The
+// path is also stored in the file, so a collision is just a miss.
+fn file_name(path: &Path) -> String {
+    // 64-bit FNV-1a, which unlike the standard library's hasher is the same in every build
+    let hash = path
+        .as_os_str()
+        .as_bytes()
+        .iter()
+        .fold(0xcbf29ce484222325_u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+    format!("config-{:016x}.cache", hash)
+}
+
+// Writes the cache to a file of its own and renames it into place, so shells writing the same
+// cache at once can't interleave, and a reader sees either the old cache or the new one
+fn write(dir: &Path, cache_path: &Path, cache: &[u8]) -> io::Result<()> {
+    config::create_cache_dir(dir)?;
+    let mut temp_path = PathBuf::from(cache_path);
+    temp_path
+        .as_mut_os_string()
+        .push(format!(".{}.tmp", process::id()));
+    fs::write(&temp_path, cache)?;
+    fs::rename(&temp_path, cache_path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp_path);
+    })
+}
+
+// Serializes an expanded config for the cache as NUL terminated fields: the magic, the key, the
+// home and the answer to each lookup, the rest of the config as TOML, and then the projects and
+// code roots, each as a count followed by three fields per entry. Optional fields start with + when
+// present and are - otherwise. Returns None if a field contains a NUL itself.
+fn encode(expanded: &ExpandedConfig, key: &Key, home: &Path, lookups: &Lookups) -> Option<Vec<u8>> {
+    let mut cache = Vec::new();
+    let mut push = |field: &[u8]| {
+        if field.contains(&0) {
+            return None;
+        }
+        cache.extend_from_slice(field);
+        cache.push(0);
+        Some(())
+    };
+
+    push(MAGIC.as_bytes())?;
+    for field in key.fields() {
+        push(&field)?;
+    }
+    push(home.as_os_str().as_bytes())?;
+    let vars = lookups.vars.borrow();
+    push(vars.len().to_string().as_bytes())?;
+    for (name, value) in vars.iter() {
+        push(name.as_bytes())?;
+        push(&optional(value.as_deref().map(OsStr::as_bytes)))?;
+    }
+    let users = lookups.users.borrow();
+    push(users.len().to_string().as_bytes())?;
+    for (name, home) in users.iter() {
+        push(name.as_bytes())?;
+        push(&optional(
+            home.as_deref().map(|home| home.as_os_str().as_bytes()),
+        ))?;
+    }
+    push(toml::to_string(&expanded.config).ok()?.as_bytes())?;
+
+    push(expanded.projects.len().to_string().as_bytes())?;
+    for project in &expanded.projects {
+        let [tag, first, second]: [&[u8]; 3] = match project {
+            Ok(ExpandedMapping::Literal { key, alias }) => {
+                [b"literal", key.as_os_str().as_bytes(), alias.as_bytes()]
+            }
+            Ok(ExpandedMapping::Pattern(mapping)) => [
+                kind_name(mapping.kind).as_bytes(),
+                mapping.path.as_bytes(),
+                mapping.alias.as_bytes(),
+            ],
+            Err(err) => error_fields(err)?,
+        };
+        push(tag)?;
+        push(first)?;
+        push(second)?;
+    }
+
+    push(expanded.code_roots.len().to_string().as_bytes())?;
+    for root in &expanded.code_roots {
+        match root {
+            Ok(root) => {
+                push(b"root")?;
+                push(root.path.as_os_str().as_bytes())?;
+                push(&optional(root.label.as_deref().map(str::as_bytes)))?;
+            }
+            Err(err) => {
+                for field in error_fields(err)? {
+                    push(field)?;
+                }
+            }
+        }
+    }
+    Some(cache)
+}
+
+// Reads an expanded config back from a cache, or returns None if the cache is for another version
+// of the file or of promptpath, was expanded with another home or with variables or users that
+// have since changed, or is damaged
+fn decode(cache: &[u8], key: &Key, home: &Path, lookups: &Lookups) -> Option<ExpandedConfig> {
+    let mut fields = cache.strip_suffix(b"\0")?.split(|&byte| byte == 0);
+    if fields.next()? != MAGIC.as_bytes() {
+        return None;
+    }
+    for expected in key.fields() {
+        if fields.next()? != expected.as_slice() {
+            return None;
+        }
+    }
+    if fields.next()? != home.as_os_str().as_bytes() {
+        return None;
+    }
+    for _ in 0..count(fields.next()?)? {
+        let (name, value) = (text(fields.next()?)?, fields.next()?);
+        let current = (lookups.var)(&name);
+        if optional(current.as_deref().map(OsStr::as_bytes)) != value {
+            return None;
+        }
+    }
+    for _ in 0..count(fields.next()?)? {
+        let (name, value) = (text(fields.next()?)?, fields.next()?);
+        let current = (lookups.user_home)(&name);
+        if optional(current.as_deref().map(|home| home.as_os_str().as_bytes())) != value {
+            return None;
+        }
+    }
+    let config: Config = toml::from_str(std::str::from_utf8(fields.next()?).ok()?).ok()?;
+
+    let projects = (0..count(fields.next()?)?)
+        .map(|_| {
+            let [tag, first, second] = [fields.next()?, fields.next()?, fields.next()?];
+            Some(match (tag, kind_from_name(tag)) {
+                (b"literal", _) => Ok(ExpandedMapping::Literal {
+                    key: PathBuf::from(OsStr::from_bytes(first)),
+                    alias: text(second)?,
+                }),
+                (_, Some(kind)) => Ok(ExpandedMapping::Pattern(ProjectMapping {
+                    path: text(first)?,
+                    alias: text(second)?,
+                    kind,
+                })),
+                (_, None) => Err(error_from_fields(tag, first, second)?),
+            })
+        })
+        .collect::<Option<_>>()?;
+
+    let code_roots = (0..count(fields.next()?)?)
+        .map(|_| {
+            let [tag, first, second] = [fields.next()?, fields.next()?, fields.next()?];
+            Some(match tag {
+                b"root" => Ok(CodeRoot {
+                    path: PathBuf::from(OsStr::from_bytes(first)),
+                    label: match second.split_first()? {
+                        (b'+', label) => Some(text(label)?),
+                        _ => None,
+                    },
+                }),
+                _ => Err(error_from_fields(tag, first, second)?),
+            })
+        })
+        .collect::<Option<_>>()?;
+
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(ExpandedConfig {
+        config,
+        projects,
+        code_roots,
+    })
+}
+
+fn optional(value: Option<&[u8]>) -> Vec<u8> {
+    match value {
+        Some(value) => [b"+", value].concat(),
+        None => b"-".to_vec(),
+    }
+}
+
+fn count(field: &[u8]) -> Option<usize> {
+    std::str::from_utf8(field).ok()?.parse().ok()
+}
+
+fn text(field: &[u8]) -> Option<String> {
+    String::from_utf8(field.to_vec()).ok()
+}
+
+// Returns the three fields a mapping that couldn't be expanded is stored as, or None for errors
+// that only come from compiling patterns, which is done after loading
+fn error_fields(err: &MappingError) -> Option<[&[u8]; 3]> {
+    match err {
+        MappingError::UnsetVariable { path, variable } => {
+            Some([b"unset-variable", path.as_bytes(), variable.as_bytes()])
+        }
+        MappingError::UnterminatedVariable(path) => {
+            Some([b"unterminated-variable", path.as_bytes(), b""])
+        }
+        MappingError::UnknownUser { path, user } => {
+            Some([b"unknown-user", path.as_bytes(), user.as_bytes()])
+        }
+        MappingError::Pattern(_) => None,
+    }
+}
+
+fn error_from_fields(tag: &[u8], first: &[u8], second: &[u8]) -> Option<MappingError> {
+    let path = text(first)?;
+    match tag {
+        b"unset-variable" => Some(MappingError::UnsetVariable {
+            path,
+            variable: text(second)?,
+        }),
+        b"unterminated-variable" => Some(MappingError::UnterminatedVariable(path)),
+        b"unknown-user" => Some(MappingError::UnknownUser {
+            path,
+            user: text(second)?,
+        }),
+        _ => None,
+    }
+}
+
+fn kind_name(kind: MappingKind) -> &'static str {
+    match kind {
+        MappingKind::Literal => "literal",
+        MappingKind::Glob => "glob",
+        MappingKind::Regex => "regex",
+    }
+}
+
+// Returns the kind of a pattern, which a literal mapping never is once expanded
+fn kind_from_name(name: &[u8]) -> Option<MappingKind> {
+    match name {
+        b"glob" => Some(MappingKind::Glob),
+        b"regex" => Some(MappingKind::Regex),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AbbreviateMode;
+    use crate::expand_config;
+    use crate::tests::scratch_dir;
+
+    const CONFIG: &str = r#"
+projects = [
+    { path = "~/code/github.com/tyler-smith/promptpath", alias = "promptpath" },
+    { path = "~/code/github.com/{org}/{repo}", alias = "{org}/{repo}", kind = "glob" },
+    { path = "~/work/(?<name>[^/]+)", alias = "work:{name}", kind = "regex" },
+    { path = "$WORKSPACE/app", alias = "app" },
+    { path = "~alice/notes", alias = "notes" },
+]
+code_roots = [{ path = "~/src", label = "src" }, { path = "$SRC" }, { path = "~/code" }]
+
+[git]
+branch = true
+
+[abbreviate]
+mode = "unique"
+"#;
+
+    fn no_vars(_: &str) -> Option<OsString> {
+        None
+    }
+
+    fn no_users(_: &str) -> Option<PathBuf> {
+        None
+    }
+
+    fn expand(config: Config, lookups: &Lookups) -> ExpandedConfig {
+        let home = Path::new("/Users/tcrypt");
+        let var = |name: &str| lookups.var(name);
+        let user_home = |name: &str| lookups.user_home(name);
+        expand_config(home, config, var, user_home)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = scratch_dir("cache-round-trip");
+        let path = dir.join("config.toml");
+        fs::write(&path, CONFIG).unwrap();
+        let key = Key::new(&path, &fs::metadata(&path).unwrap());
+        let home = Path::new("/Users/tcrypt");
+
+        let lookups = Lookups::with(no_vars, |name| {
+            (name == "alice").then(|| PathBuf::from("/home/alice"))
+        });
+        let expanded = expand(config::load(&path).unwrap(), &lookups);
+        let cache = encode(&expanded, &key, home, &lookups).unwrap();
+        let decoded = decode(&cache, &key, home, &lookups).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", expanded));
+        assert!(matches!(
+            &decoded.projects[0],
+            Ok(ExpandedMapping::Literal { key, .. })
+                if key == Path::new("/Users/tcrypt/code/github.com/tyler-smith/promptpath")
+        ));
+        assert!(matches!(
+            &decoded.projects[3],
+            Err(MappingError::UnsetVariable { variable, .. }) if variable == "WORKSPACE"
+        ));
+        assert!(matches!(
+            &decoded.projects[4],
+            Ok(ExpandedMapping::Literal { key, .. }) if key == Path::new("/home/alice/notes")
+        ));
+        assert_eq!(decoded.code_roots.len(), 3);
+        assert_eq!(decoded.config.abbreviate.mode, AbbreviateMode::Unique);
+
+        // Caches for another version of the file, another home, or with variables or users that
+        // have changed since, or cut short, are ignored
+        let other_key = Key {
+            path: key.path.clone(),
+            len: 1,
+            ..key
+        };
+        assert!(decode(&cache, &other_key, home, &lookups).is_none());
+        assert!(decode(&cache, &key, Path::new("/home/tcrypt"), &lookups).is_none());
+        let workspace = |name: &str| (name == "WORKSPACE").then(|| OsString::from("/w"));
+        assert!(decode(&cache, &key, home, &Lookups::with(workspace, no_users)).is_none());
+        assert!(decode(&cache, &key, home, &Lookups::with(no_vars, no_users)).is_none());
+        assert!(decode(&cache[..cache.len() - 2], &key, home, &lookups).is_none());
+        assert!(decode(b"", &key, home, &lookups).is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = scratch_dir("cache-load");
+        let path = dir.join("config.toml");
+        let cache_dir = dir.join("cache");
+        let home = Path::new("/Users/tcrypt");
+        let contents = CONFIG.replace("$WORKSPACE", "~").replace("$SRC", "~/s");
+        fs::write(&path, &contents).unwrap();
+
+        // A config that was only just written isn't cached yet
+        let projects = |cache_dir: &Path| {
+            load(Some(cache_dir), &path, home, expand)
+                .unwrap()
+                .projects
+                .len()
+        };
+        assert_eq!(projects(&cache_dir), 5);
+        assert!(!cache_dir.exists());
+
+        let old = std::time::SystemTime::now() - MIN_AGE * 2;
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert_eq!(projects(&cache_dir), 5);
+        let cache_path = cache_dir.join(file_name(&path));
+        assert!(cache_path.exists());
+
+        // The cache is used while the file is unchanged
+        let key = Key::new(&path, &fs::metadata(&path).unwrap());
+        let lookups = Lookups::new();
+        let mut expanded = decode(&fs::read(&cache_path).unwrap(), &key, home, &lookups).unwrap();
+        expanded.projects.truncate(1);
+        let cache = encode(&expanded, &key, home, &lookups).unwrap();
+        fs::write(&cache_path, cache).unwrap();
+        assert_eq!(projects(&cache_dir), 1);
+
+        // Editing the file invalidates it
+        fs::write(&path, contents.replace("[git]\nbranch = true\n", "")).unwrap();
+        let expanded = load(Some(&cache_dir), &path, home, expand).unwrap();
+        assert_eq!(expanded.projects.len(), 5);
+        assert!(!expanded.config.git.branch);
+
+        let missing = dir.join("missing.toml");
+        let result = load(Some(&cache_dir), &missing, home, |_, _| unreachable!());
+        assert!(result.unwrap_err().is_missing());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
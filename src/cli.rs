@@ -0,0 +1,575 @@
+use crate::shell::{Escape, Shell};
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+pub const USAGE: &str = "\
+Usage: promptpath [OPTIONS] [PATH...]
+       promptpath check [--config FILE]
+       promptpath init SHELL [--daemon]
+       promptpath daemon [--config FILE]
+       promptpath bench
+
+Prints a short nickname for each PATH, one per line, or for the current
+directory when no PATH is given.
+
+Commands:
+  check            Check the config file for errors
+  init SHELL       Print the integration script for SHELL (bash, zsh, fish,
+                   nushell or elvish), e.g. eval \"$(promptpath init bash)\".
+                   With --daemon, the script starts a daemon and uses it.
+  daemon           Keep the config loaded, reloading it when it changes, and
+                   answer --daemon queries on a socket in $XDG_RUNTIME_DIR
+  bench            Time loading the config and looking up nicknames with 100
+                   to 100,000 generated project mappings
+
+Options:
+      --stdin      Read paths from stdin, one per line, instead of arguments
+  -0, --null       With --stdin, paths and nicknames are separated by NUL
+                   instead of newlines
+      --json       With --stdin, print one JSON object per path with its
+                   nickname, alias and the rule that matched
+  -P, --physical   Resolve symlinks in the current directory instead of using
+                   $PWD, the path the shell took to get there
+      --shell SHELL
+                   Escape the output for embedding in a bash, zsh or fish
+                   prompt string, or with plain only make control
+                   characters visible
+      --daemon     Ask a running daemon for the nicknames, computing them here
+                   if there's none or it loaded a different config file
+      --explain    Show which config file was used and how each nickname
+                   was produced
+      --config FILE
+                   Read the config from FILE instead of PROMPTPATH_CONFIG,
+                   $XDG_CONFIG_HOME/promptpath/config.toml or
+                   ~/.config/promptpath/config.toml
+  -h, --help       Print this help and exit
+  -V, --version    Print the version and exit
+";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CliError {
+    #[error("unknown option '{0}'")]
+    UnknownOption(String),
+    #[error("'{0}' can only be used with --stdin")]
+    RequiresStdin(&'static str),
+    #[error("'{0}' can't be used with --stdin")]
+    ConflictsWithStdin(&'static str),
+    #[error("paths can't be given as arguments with --stdin")]
+    PathsWithStdin,
+    #[error("unexpected argument '{0}'")]
+    UnexpectedArgument(String),
+    #[error("'{0}' requires a value")]
+    MissingValue(&'static str),
+    #[error("init requires a shell")]
+    MissingShell,
+    #[error("unsupported shell '{0}'")]
+    UnknownShell(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub command: Command,
+    // Config file given with --config
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    // Print the nicknames of the given paths, or of the current directory if there are none,
+    // escaped for a prompt if escape is set. With explain, print how each nickname was produced
+    // instead. With physical, the current directory has its symlinks resolved rather than being
+    // taken from $PWD.
+    Print {
+        paths: Vec<PathBuf>,
+        explain: bool,
+        escape: Option<Escape>,
+        physical: bool,
+        // Ask the daemon first
+        daemon: bool,
+    },
+    // Print the nicknames of paths read from stdin, separated by delimiter
+    Batch {
+        delimiter: u8,
+        json: bool,
+        physical: bool,
+    },
+    // Check the config file for errors
+    Check,
+    // Print the integration script for shell, using the daemon if daemon is set
+    Init {
+        shell: Shell,
+        daemon: bool,
+    },
+    // Answer queries from clients run with --daemon until killed
+    Daemon,
+    // Time loading the config and looking up nicknames with growing numbers of project mappings
+    Bench,
+    Help,
+    Version,
+}
+
+// Parses the command line arguments, not including the program name
+pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Args, CliError> {
+    let mut config = None;
+    let mut args = args.into_iter().peekable();
+
+    // Commands are only recognized as the first argument, so ./check still names a directory
+    let command = if args.peek().is_some_and(|arg| arg == "check") {
+        args.next();
+        parse_command_args(args, &mut config, Command::Check)?
+    } else if args.peek().is_some_and(|arg| arg == "init") {
+        args.next();
+        match parse_init_shell(args.next())? {
+            Command::Init { shell, .. } => parse_init_args(args, &mut config, shell)?,
+            command => command,
+        }
+    } else if args.peek().is_some_and(|arg| arg == "daemon") {
+        args.next();
+        parse_command_args(args, &mut config, Command::Daemon)?
+    } else if args.peek().is_some_and(|arg| arg == "bench") {
+        args.next();
+        parse_command_args(args, &mut config, Command::Bench)?
+    } else {
+        parse_print_args(args, &mut config)?
+    };
+
+    Ok(Args { command, config })
+}
+
+// Parses the arguments for printing nicknames, which is what happens without a command
+fn parse_print_args(
+    args: impl IntoIterator<Item = OsString>,
+    config: &mut Option<PathBuf>,
+) -> Result<Command, CliError> {
+    let mut paths = Vec::new();
+    let mut stdin = false;
+    let mut null = false;
+    let mut json = false;
+    let mut explain = false;
+    let mut escape = None;
+    let mut physical = false;
+    let mut daemon = false;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if parse_global_option(&arg, &mut args, config)? {
+            continue;
+        }
+        match arg.to_str() {
+            Some("-h" | "--help") => return Ok(Command::Help),
+            Some("-V" | "--version") => return Ok(Command::Version),
+            Some("--stdin") => stdin = true,
+            Some("-0" | "--null") => null = true,
+            Some("--json") => json = true,
+            Some("--explain") => explain = true,
+            Some("-P" | "--physical") => physical = true,
+            Some("--daemon") => daemon = true,
+            Some("--shell") => {
+                let value = args.next().ok_or(CliError::MissingValue("--shell"))?;
+                escape = Some(parse_escape(&value.to_string_lossy())?);
+            }
+            Some(option) if option.starts_with("--shell=") => {
+                escape = Some(parse_escape(&option["--shell=".len()..])?);
+            }
+            Some("--") => {
+                paths.extend(args.by_ref().map(PathBuf::from));
+            }
+            Some(option) if option.starts_with('-') && option != "-" => {
+                return Err(CliError::UnknownOption(option.to_string()));
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if stdin {
+        if !paths.is_empty() {
+            return Err(CliError::PathsWithStdin);
+        }
+        if escape.is_some() {
+            return Err(CliError::ConflictsWithStdin("--shell"));
+        }
+        if daemon {
+            return Err(CliError::ConflictsWithStdin("--daemon"));
+        }
+        let delimiter = if null { b'\0' } else { b'\n' };
+        return Ok(Command::Batch {
+            delimiter,
+            json,
+            physical,
+        });
+    }
+    if null {
+        return Err(CliError::RequiresStdin("--null"));
+    }
+    if json {
+        return Err(CliError::RequiresStdin("--json"));
+    }
+
+    Ok(Command::Print {
+        paths,
+        explain,
+        escape,
+        physical,
+        daemon,
+    })
+}
+
+// Parses the value of --shell
+fn parse_escape(name: &str) -> Result<Escape, CliError> {
+    match name {
+        "" => Err(CliError::MissingValue("--shell")),
+        name => Escape::from_name(name).ok_or_else(|| CliError::UnknownShell(name.to_string())),
+    }
+}
+
+// Parses the shell name given to init
+fn parse_init_shell(arg: Option<OsString>) -> Result<Command, CliError> {
+    let arg = arg.ok_or(CliError::MissingShell)?;
+    match arg.to_str() {
+        Some("-h" | "--help") => Ok(Command::Help),
+        Some(name) => match Shell::from_name(name) {
+            Some(shell) => Ok(Command::Init {
+                shell,
+                daemon: false,
+            }),
+            None => Err(CliError::UnknownShell(name.to_string())),
+        },
+        None => Err(CliError::UnknownShell(arg.to_string_lossy().into_owned())),
+    }
+}
+
+// Parses the arguments following init SHELL
+fn parse_init_args(
+    args: impl IntoIterator<Item = OsString>,
+    config: &mut Option<PathBuf>,
+    shell: Shell,
+) -> Result<Command, CliError> {
+    let mut daemon = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if parse_global_option(&arg, &mut args, config)? {
+            continue;
+        }
+        match arg.to_str() {
+            Some("-h" | "--help") => return Ok(Command::Help),
+            Some("--daemon") => daemon = true,
+            _ => {
+                return Err(CliError::UnexpectedArgument(
+                    arg.to_string_lossy().into_owned(),
+                ))
+            }
+        }
+    }
+    Ok(Command::Init { shell, daemon })
+}
+
+// Parses the arguments following a command that takes no arguments beyond the global options
+fn parse_command_args(
+    args: impl IntoIterator<Item = OsString>,
+    config: &mut Option<PathBuf>,
+    command: Command,
+) -> Result<Command, CliError> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if parse_global_option(&arg, &mut args, config)? {
+            continue;
+        }
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        return Err(CliError::UnexpectedArgument(
+            arg.to_string_lossy().into_owned(),
+        ));
+    }
+    Ok(command)
+}
+
+// Parses an option accepted by every command, taking its value from args if needed. Returns
+// false if arg isn't a global option.
+fn parse_global_option(
+    arg: &OsString,
+    args: &mut impl Iterator<Item = OsString>,
+    config: &mut Option<PathBuf>,
+) -> Result<bool, CliError> {
+    let Some(arg) = arg.to_str() else {
+        return Ok(false);
+    };
+
+    if arg == "--config" {
+        let value = args.next().ok_or(CliError::MissingValue("--config"))?;
+        *config = Some(PathBuf::from(value));
+        return Ok(true);
+    }
+    if let Some(value) = arg.strip_prefix("--config=") {
+        if value.is_empty() {
+            return Err(CliError::MissingValue("--config"));
+        }
+        *config = Some(PathBuf::from(value));
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+// Makes path absolute relative to cwd and removes . and .. components lexically, the same way a
+// shell's cd would
+pub fn normalize_path(cwd: &Path, path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in cwd.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let test_cases = [
+            (
+                vec![],
+                Ok(Command::Print {
+                    paths: vec![],
+                    explain: false,
+                    escape: None,
+                    physical: false,
+                    daemon: false,
+                }),
+            ),
+            (
+                vec!["/tmp", "src"],
+                Ok(Command::Print {
+                    paths: vec!["/tmp".into(), "src".into()],
+                    explain: false,
+                    escape: None,
+                    physical: false,
+                    daemon: false,
+                }),
+            ),
+            (
+                vec!["--", "--help", "-"],
+                Ok(Command::Print {
+                    paths: vec!["--help".into(), "-".into()],
+                    explain: false,
+                    escape: None,
+                    physical: false,
+                    daemon: false,
+                }),
+            ),
+            (
+                vec!["--explain", "/tmp"],
+                Ok(Command::Print {
+                    paths: vec!["/tmp".into()],
+                    explain: true,
+                    escape: None,
+                    physical: false,
+                    daemon: false,
+                }),
+            ),
+            (
+                vec!["--shell", "zsh", "/tmp"],
+                Ok(Command::Print {
+                    paths: vec!["/tmp".into()],
+                    explain: false,
+                    escape: Some(Escape::Zsh),
+                    physical: false,
+                    daemon: false,
+                }),
+            ),
+            (
+                vec!["--shell=plain"],
+                Ok(Command::Print {
+                    paths: vec![],
+                    explain: false,
+                    escape: Some(Escape::Plain),
+                    physical: false,
+                    daemon: false,
+                }),
+            ),
+            (vec!["--shell"], Err(CliError::MissingValue("--shell"))),
+            (
+                vec!["--shell=csh"],
+                Err(CliError::UnknownShell("csh".into())),
+            ),
+            (
+                vec!["--stdin", "--shell", "bash"],
+                Err(CliError::ConflictsWithStdin("--shell")),
+            ),
+            (
+                vec!["-P", "--stdin"],
+                Ok(Command::Batch {
+                    delimiter: b'\n',
+                    json: false,
+                    physical: true,
+                }),
+            ),
+            (
+                vec!["--physical"],
+                Ok(Command::Print {
+                    paths: vec![],
+                    explain: false,
+                    escape: None,
+                    physical: true,
+                    daemon: false,
+                }),
+            ),
+            (vec!["/tmp", "--help"], Ok(Command::Help)),
+            (vec!["-h"], Ok(Command::Help)),
+            (vec!["--version"], Ok(Command::Version)),
+            (vec!["-V"], Ok(Command::Version)),
+            (
+                vec!["--stdin"],
+                Ok(Command::Batch {
+                    delimiter: b'\n',
+                    json: false,
+                    physical: false,
+                }),
+            ),
+            (
+                vec!["--json", "--stdin", "-0"],
+                Ok(Command::Batch {
+                    delimiter: b'\0',
+                    json: true,
+                    physical: false,
+                }),
+            ),
+            (vec!["--stdin", "/tmp"], Err(CliError::PathsWithStdin)),
+            (vec!["--json"], Err(CliError::RequiresStdin("--json"))),
+            (
+                vec!["--null", "/tmp"],
+                Err(CliError::RequiresStdin("--null")),
+            ),
+            (vec!["check"], Ok(Command::Check)),
+            (vec!["check", "--help"], Ok(Command::Help)),
+            (
+                vec!["check", "/tmp"],
+                Err(CliError::UnexpectedArgument("/tmp".into())),
+            ),
+            (
+                vec!["init", "zsh"],
+                Ok(Command::Init {
+                    shell: Shell::Zsh,
+                    daemon: false,
+                }),
+            ),
+            (
+                vec!["init", "nu", "--daemon"],
+                Ok(Command::Init {
+                    shell: Shell::Nushell,
+                    daemon: true,
+                }),
+            ),
+            (
+                vec!["--daemon", "--shell=plain"],
+                Ok(Command::Print {
+                    paths: vec![],
+                    explain: false,
+                    escape: Some(Escape::Plain),
+                    physical: false,
+                    daemon: true,
+                }),
+            ),
+            (
+                vec!["--stdin", "--daemon"],
+                Err(CliError::ConflictsWithStdin("--daemon")),
+            ),
+            (vec!["daemon"], Ok(Command::Daemon)),
+            (
+                vec!["daemon", "/tmp"],
+                Err(CliError::UnexpectedArgument("/tmp".into())),
+            ),
+            (vec!["bench"], Ok(Command::Bench)),
+            (
+                vec!["bench", "--json"],
+                Err(CliError::UnexpectedArgument("--json".into())),
+            ),
+            (vec!["init"], Err(CliError::MissingShell)),
+            (vec!["init", "--help"], Ok(Command::Help)),
+            (
+                vec!["init", "tcsh"],
+                Err(CliError::UnknownShell("tcsh".into())),
+            ),
+            (
+                vec!["init", "bash", "zsh"],
+                Err(CliError::UnexpectedArgument("zsh".into())),
+            ),
+            (
+                vec!["./check"],
+                Ok(Command::Print {
+                    paths: vec!["./check".into()],
+                    explain: false,
+                    escape: None,
+                    physical: false,
+                    daemon: false,
+                }),
+            ),
+            (
+                vec!["--bogus"],
+                Err(CliError::UnknownOption("--bogus".into())),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            let result = parse(args(&input)).map(|args| args.command);
+            assert_eq!(result, expected, "Failed test: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let test_cases = [
+            (vec!["/tmp"], Ok(None)),
+            (
+                vec!["--config", "a.toml", "/tmp"],
+                Ok(Some("a.toml".into())),
+            ),
+            (vec!["/tmp", "--config=b.toml"], Ok(Some("b.toml".into()))),
+            (
+                vec!["check", "--config", "c.toml"],
+                Ok(Some("c.toml".into())),
+            ),
+            (vec!["--config"], Err(CliError::MissingValue("--config"))),
+            (vec!["--config="], Err(CliError::MissingValue("--config"))),
+        ];
+
+        for (input, expected) in test_cases {
+            let result = parse(args(&input)).map(|args| args.config);
+            assert_eq!(result, expected, "Failed test: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let cwd = Path::new("/Users/tcrypt/code");
+        let test_cases = [
+            ("/usr/bin", "/usr/bin"),
+            ("/usr/bin/", "/usr/bin"),
+            ("github.com", "/Users/tcrypt/code/github.com"),
+            (".", "/Users/tcrypt/code"),
+            ("..", "/Users/tcrypt"),
+            ("./a/../b/./c", "/Users/tcrypt/code/b/c"),
+            ("/../..", "/"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(
+                normalize_path(cwd, Path::new(input)),
+                PathBuf::from(expected),
+                "Failed test: {:?}",
+                input
+            );
+        }
+    }
+}
@@ -0,0 +1,867 @@
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::passwd;
+use crate::pattern::PatternError;
+
+const CONFIG_PATH: &str = ".config/promptpath/config.toml";
+const XDG_CONFIG_PATH: &str = "promptpath/config.toml";
+const CONFIG_ENV: &str = "PROMPTPATH_CONFIG";
+const DEFAULT_CODE_ROOT: &str = "~/code";
+
+// How often a broken config file is reported while rendering prompts
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+const WARNING_MARKER: &str = "config-warning";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read config file {}: {source}", path.display())]
+    FileRead { path: PathBuf, source: io::Error },
+    #[error("Failed to parse config file {}:{line}:{column}: {message}", path.display())]
+    ParseError {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("Invalid mapping in {}: {source}", path.display())]
+    InvalidMapping { path: PathBuf, source: MappingError },
+}
+
+// A problem with a single project mapping or code root, which is skipped rather than failing the
+// whole config
+#[derive(Error, Debug, PartialEq)]
+pub enum MappingError {
+    #[error(transparent)]
+    Pattern(#[from] PatternError),
+    #[error("'{path}' uses ${variable}, which isn't set")]
+    UnsetVariable { path: String, variable: String },
+    #[error("'{0}' has an unterminated ${{...}}")]
+    UnterminatedVariable(String),
+    #[error("'{path}' uses ~{user}, which isn't a known user")]
+    UnknownUser { path: String, user: String },
+}
+
+impl MappingError {
+    // Returns true for errors that come from the environment the config is used in rather than
+    // the config itself, like a variable that's only set on some machines
+    pub fn depends_on_environment(&self) -> bool {
+        matches!(
+            self,
+            MappingError::UnsetVariable { .. } | MappingError::UnknownUser { .. }
+        )
+    }
+}
+
+impl ConfigError {
+    // Returns true if the config file doesn't exist, which just means the defaults are used
+    pub fn is_missing(&self) -> bool {
+        matches!(self, ConfigError::FileRead { source, .. } if source.kind() == io::ErrorKind::NotFound)
+    }
+}
+
+// Where the location of the config file came from, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigSource {
+    Flag,
+    Env,
+    XdgConfigHome,
+    Default,
+    // The invoking user's default config, used under sudo
+    SudoUser,
+}
+
+impl ConfigSource {
+    pub fn describe(self) -> &'static str {
+        match self {
+            ConfigSource::Flag => "--config",
+            ConfigSource::Env => CONFIG_ENV,
+            ConfigSource::XdgConfigHome => "XDG_CONFIG_HOME",
+            ConfigSource::Default => "default",
+            ConfigSource::SudoUser => "SUDO_USER",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ConfigLocation {
+    pub path: PathBuf,
+    pub source: ConfigSource,
+}
+
+impl ConfigLocation {
+    // Returns true if the user asked for this file specifically, in which case it not existing is
+    // an error rather than a reason to use the defaults
+    pub fn is_explicit(&self) -> bool {
+        matches!(self.source, ConfigSource::Flag | ConfigSource::Env)
+    }
+}
+
+// Finds the config file from the --config flag, then PROMPTPATH_CONFIG, then
+// $XDG_CONFIG_HOME/promptpath/config.toml, and finally ~/.config/promptpath/config.toml. Under
+// sudo, sudo_home is the invoking user's home, whose default config is used when the effective
+// user has none of their own.
+pub fn locate(flag: Option<&Path>, home: &Path, sudo_home: Option<&Path>) -> ConfigLocation {
+    locate_with(flag, home, sudo_home, |name| env::var_os(name))
+}
+
+fn locate_with(
+    flag: Option<&Path>,
+    home: &Path,
+    sudo_home: Option<&Path>,
+    var: impl Fn(&str) -> Option<OsString>,
+) -> ConfigLocation {
+    if let Some(path) = flag {
+        return ConfigLocation {
+            path: path.to_path_buf(),
+            source: ConfigSource::Flag,
+        };
+    }
+
+    if let Some(path) = var(CONFIG_ENV).filter(|path| !path.is_empty()) {
+        return ConfigLocation {
+            path: PathBuf::from(path),
+            source: ConfigSource::Env,
+        };
+    }
+
+    // The XDG spec says relative paths are invalid and should be ignored
+    let xdg_config_home = var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute());
+    if let Some(dir) = xdg_config_home {
+        return ConfigLocation {
+            path: dir.join(XDG_CONFIG_PATH),
+            source: ConfigSource::XdgConfigHome,
+        };
+    }
+
+    let path = home.join(CONFIG_PATH);
+    if let Some(sudo_home) = sudo_home.filter(|_| !path.exists()) {
+        return ConfigLocation {
+            path: sudo_home.join(CONFIG_PATH),
+            source: ConfigSource::SudoUser,
+        };
+    }
+    ConfigLocation {
+        path,
+        source: ConfigSource::Default,
+    }
+}
+
+// Loads and parses the config file at path
+pub fn load(path: &Path) -> Result<Config, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(|source| ConfigError::FileRead {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse(path, &contents)
+}
+
+fn parse(path: &Path, contents: &str) -> Result<Config, ConfigError> {
+    toml::from_str(contents).map_err(|err| {
+        let offset = err.span().map_or(0, |span| span.start);
+        let (line, column) = line_column(contents, offset);
+        ConfigError::ParseError {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message: one_line(err.message()),
+        }
+    })
+}
+
+// Joins the lines of a message from toml, which can span several, so the warning shown in a
+// prompt stays on one line
+fn one_line(message: &str) -> String {
+    message
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Converts a byte offset into 1-based line and column numbers, counting columns in characters
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+// Expands $VAR, ${VAR} and ${VAR:-default} in a config path using var to look up variables. The
+// default is used when the variable is unset or empty. A $ not followed by a variable name is left
+// as is. Values are passed through escape before being inserted, so they can be quoted for use in
+// a regex.
+pub fn expand_vars(
+    path: &str,
+    var: impl Fn(&str) -> Option<OsString>,
+    escape: impl Fn(&str) -> String,
+) -> Result<String, MappingError> {
+    let mut result = String::with_capacity(path.len());
+    let mut rest = path;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let (name, default, len) = if let Some(braced) = after.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| MappingError::UnterminatedVariable(path.to_string()))?;
+            let inner = &braced[..end];
+            match inner.split_once(":-") {
+                Some((name, default)) => (name, Some(default), end + 2),
+                None => (inner, None, end + 2),
+            }
+        } else {
+            let len = after
+                .char_indices()
+                .take_while(|(i, c)| {
+                    c.is_ascii_alphabetic() || *c == '_' || (*i > 0 && c.is_ascii_digit())
+                })
+                .count();
+            (&after[..len], None, len)
+        };
+
+        if name.is_empty() {
+            result.push('$');
+            rest = after;
+            continue;
+        }
+
+        let value = var(name).filter(|value| default.is_none() || !value.is_empty());
+        match (value, default) {
+            (Some(value), _) => result.push_str(&escape(&value.to_string_lossy())),
+            (None, Some(default)) => result.push_str(default),
+            (None, None) => {
+                return Err(MappingError::UnsetVariable {
+                    path: path.to_string(),
+                    variable: name.to_string(),
+                })
+            }
+        }
+        rest = &after[len..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+// Expands a leading ~user in a config path to that user's home directory, using user_home to look
+// it up. The home is passed through escape like expand_vars values. A bare ~ or ~/ is left as is
+// since it stands for the current user's home, which is expanded later.
+pub fn expand_user(
+    path: &str,
+    user_home: impl Fn(&str) -> Option<PathBuf>,
+    escape: impl Fn(&str) -> String,
+) -> Result<String, MappingError> {
+    let Some(rest) = path.strip_prefix('~') else {
+        return Ok(path.to_string());
+    };
+    let (user, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if user.is_empty() {
+        return Ok(path.to_string());
+    }
+
+    let home = user_home(user).ok_or_else(|| MappingError::UnknownUser {
+        path: path.to_string(),
+        user: user.to_string(),
+    })?;
+    Ok(format!("{}{}", escape(&home.to_string_lossy()), rest))
+}
+
+// Returns $XDG_CACHE_HOME/promptpath, or ~/.cache/promptpath if that isn't set, or None if
+// there's no home either
+pub fn cache_dir(home: Option<&Path>) -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| Some(home?.join(".cache")))?;
+    Some(dir.join("promptpath"))
+}
+
+// Creates the cache directory dir if it's missing. Fails without creating anything if the closest
+// part of it that exists belongs to another user, as when root runs under sudo -E with the
+// invoking user's HOME, so root doesn't leave files there that the user can't replace. Shared
+// directories with the sticky bit set, like /tmp, are fine, as only their owner can touch what's
+// created in them.
+pub fn create_cache_dir(dir: &Path) -> io::Result<()> {
+    let existing = dir
+        .ancestors()
+        .find_map(|dir| fs::metadata(dir).ok())
+        .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+    if existing.uid() != passwd::effective_uid() && existing.mode() & libc::S_ISVTX == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the cache directory belongs to another user",
+        ));
+    }
+    fs::create_dir_all(dir)
+}
+
+// Prints a one-line warning about a config error to stderr, at most once per WARNING_INTERVAL so
+// a broken config doesn't flood every prompt. A marker file in the cache directory records when
+// the last warning was shown. Editing the config resets the interval so fixes are confirmed, or
+// new mistakes reported, straight away.
+pub fn warn_throttled(home: Option<&Path>, err: &ConfigError) {
+    let cache_dir = cache_dir(home);
+    let marker = cache_dir.as_ref().map(|dir| dir.join(WARNING_MARKER));
+
+    let last_warning = marker
+        .as_ref()
+        .and_then(|marker| fs::metadata(marker).and_then(|m| m.modified()).ok());
+    if let Some(last_warning) = last_warning {
+        let config_path = match err {
+            ConfigError::FileRead { path, .. }
+            | ConfigError::ParseError { path, .. }
+            | ConfigError::InvalidMapping { path, .. } => path,
+        };
+        let config_modified = fs::metadata(config_path).and_then(|m| m.modified()).ok();
+        let recent = last_warning
+            .elapsed()
+            .is_ok_and(|elapsed| elapsed < WARNING_INTERVAL);
+        let config_unchanged = config_modified.is_none_or(|modified| modified <= last_warning);
+        if recent && config_unchanged {
+            return;
+        }
+    }
+
+    eprintln!("promptpath: {} (run 'promptpath check' for details)", err);
+    // Without a cache directory of this user's the warning is shown on every prompt
+    if let (Some(cache_dir), Some(marker)) = (cache_dir, marker) {
+        if create_cache_dir(&cache_dir).is_ok() {
+            let _ = fs::write(marker, b"");
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub projects: Vec<ProjectMapping>,
+    #[serde(default = "default_code_roots")]
+    pub code_roots: Vec<CodeRootMapping>,
+    #[serde(default)]
+    pub git: GitConfig,
+    #[serde(default)]
+    pub abbreviate: AbbreviateConfig,
+    #[serde(default)]
+    pub invalid_utf8: InvalidUtf8Config,
+    #[serde(default)]
+    pub paths: PathsConfig,
+    #[serde(default)]
+    pub sudo: SudoConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            projects: Vec::new(),
+            code_roots: default_code_roots(),
+            git: GitConfig::default(),
+            abbreviate: AbbreviateConfig::default(),
+            invalid_utf8: InvalidUtf8Config::default(),
+            paths: PathsConfig::default(),
+            sudo: SudoConfig::default(),
+        }
+    }
+}
+
+fn default_code_roots() -> Vec<CodeRootMapping> {
+    vec![CodeRootMapping {
+        path: DEFAULT_CODE_ROOT.to_string(),
+        label: None,
+    }]
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectMapping {
+    pub path: String,
+    pub alias: String,
+    #[serde(default)]
+    pub kind: MappingKind,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MappingKind {
+    // path names a single directory
+    #[default]
+    Literal,
+    // path is a glob such as ~/code/github.com/{org}/{repo}
+    Glob,
+    // path is a regex matched against the start of the full path
+    Regex,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CodeRootMapping {
+    pub path: String,
+    pub label: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GitConfig {
+    // Automatically alias git repositories as <repo-dir-name>/<subpath>
+    #[serde(default)]
+    pub alias: bool,
+    // Text shown in front of automatic repository aliases, e.g. "@"
+    #[serde(default)]
+    pub alias_prefix: String,
+    // Show the current branch after the nickname
+    #[serde(default)]
+    pub branch: bool,
+    // Format of the branch segment, where {branch} is replaced with the branch name
+    #[serde(default = "default_branch_format")]
+    pub branch_format: String,
+    // Format of the branch segment for a detached HEAD, where {branch} is replaced with the tag
+    // pointing at HEAD or the abbreviated commit hash
+    #[serde(default = "default_detached_format")]
+    pub detached_format: String,
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        Self {
+            alias: false,
+            alias_prefix: String::new(),
+            branch: false,
+            branch_format: default_branch_format(),
+            detached_format: default_detached_format(),
+        }
+    }
+}
+
+fn default_branch_format() -> String {
+    String::from(" ({branch})")
+}
+
+fn default_detached_format() -> String {
+    String::from(" (({branch}))")
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AbbreviateConfig {
+    #[serde(default)]
+    pub mode: AbbreviateMode,
+    // Number of trailing components that are never abbreviated
+    #[serde(default = "default_keep_full")]
+    pub keep_full: usize,
+    // Number of characters abbreviated components are shortened to
+    #[serde(default = "default_abbreviate_length")]
+    pub length: usize,
+    // Directories with more entries than this are left in full by the unique mode
+    #[serde(default = "default_max_dir_entries")]
+    pub max_dir_entries: usize,
+}
+
+impl Default for AbbreviateConfig {
+    fn default() -> Self {
+        Self {
+            mode: AbbreviateMode::default(),
+            keep_full: default_keep_full(),
+            length: default_abbreviate_length(),
+            max_dir_entries: default_max_dir_entries(),
+        }
+    }
+}
+
+fn default_keep_full() -> usize {
+    1
+}
+
+fn default_abbreviate_length() -> usize {
+    1
+}
+
+fn default_max_dir_entries() -> usize {
+    1000
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AbbreviateMode {
+    // Show every component in full
+    #[default]
+    None,
+    // Shorten components to their first characters, like fish's prompt_pwd
+    Fish,
+    // Shorten components to the shortest prefix unique among their siblings on disk, like zsh's
+    // shrink-path
+    Unique,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PathsConfig {
+    // When a path doesn't match any project mapping, try again with its symlinks resolved, so
+    // mappings can name either the directory a symlink points to or the symlink
+    #[serde(default)]
+    pub match_physical: bool,
+    // Collapse other users' home directories to ~user
+    #[serde(default)]
+    pub user_homes: bool,
+    // Shown after the last known nickname when the current directory has been deleted
+    #[serde(default = "default_deleted_marker")]
+    pub deleted_marker: String,
+    // Shown after the last known nickname when the current directory can't be accessed
+    #[serde(default = "default_no_access_marker")]
+    pub no_access_marker: String,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        Self {
+            match_physical: false,
+            user_homes: false,
+            deleted_marker: default_deleted_marker(),
+            no_access_marker: default_no_access_marker(),
+        }
+    }
+}
+
+fn default_deleted_marker() -> String {
+    String::from(" (deleted)")
+}
+
+fn default_no_access_marker() -> String {
+    String::from(" (no access)")
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SudoConfig {
+    // Shown after the path when running as root, such as under sudo. Empty shows nothing.
+    #[serde(default)]
+    pub root_marker: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct InvalidUtf8Config {
+    #[serde(default)]
+    pub mode: InvalidUtf8Mode,
+    // Text shown in place of each invalid sequence by the replace mode. It shouldn't contain a /
+    // since nicknames are split on it.
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+}
+
+impl Default for InvalidUtf8Config {
+    fn default() -> Self {
+        Self {
+            mode: InvalidUtf8Mode::default(),
+            replacement: default_replacement(),
+        }
+    }
+}
+
+fn default_replacement() -> String {
+    String::from("\u{fffd}")
+}
+
+// How bytes in paths that aren't valid UTF-8 are shown
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InvalidUtf8Mode {
+    // Show each byte as \xNN
+    #[default]
+    Escape,
+    // Show the replacement text in place of each invalid sequence
+    Replace,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::scratch_dir;
+
+    #[test]
+    fn test_parse() {
+        let path = Path::new("/Users/tcrypt/.config/promptpath/config.toml");
+        let contents = r#"
+projects = [
+    { path = "~/code/github.com/tyler-smith/promptpath", alias = "promptpath" }
+]
+
+[git]
+branch = true
+
+[invalid_utf8]
+mode = "replace"
+
+[sudo]
+root_marker = " #"
+"#;
+        let config = parse(path, contents).unwrap();
+        assert_eq!(config.projects.len(), 1);
+        assert_eq!(config.code_roots.len(), 1);
+        assert!(config.git.branch);
+        assert!(!config.git.alias);
+        assert_eq!(config.invalid_utf8.mode, InvalidUtf8Mode::Replace);
+        assert_eq!(config.invalid_utf8.replacement, "\u{fffd}");
+        assert_eq!(config.sudo.root_marker, " #");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let path = Path::new("/Users/tcrypt/.config/promptpath/config.toml");
+        let test_cases = [
+            (
+                "projects = [\n  { path = \"~/x\" alias = \"x\" }\n]\n",
+                2,
+                18,
+            ),
+            ("project = []\n", 1, 1),
+            ("[git]\nbranch = true\nbrnach_format = \"\"\n", 3, 1),
+            ("[abbreviate]\nmode = \"zsh\"\n", 2, 8),
+            ("projects = [\n", 2, 1),
+        ];
+
+        for (contents, expected_line, expected_column) in test_cases {
+            match parse(path, contents) {
+                Err(ConfigError::ParseError {
+                    line,
+                    column,
+                    message,
+                    ..
+                }) => assert_eq!(
+                    (line, column, message.contains('\n')),
+                    (expected_line, expected_column, false),
+                    "Failed test: {:?}",
+                    contents
+                ),
+                result => panic!("Failed test {:?}: got {:?}", contents, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_missing() {
+        let err = load(Path::new("/nonexistent/promptpath/config.toml")).unwrap_err();
+        assert!(err.is_missing());
+
+        let err = parse(Path::new("config.toml"), "projects = 1").unwrap_err();
+        assert!(!err.is_missing());
+    }
+
+    #[test]
+    fn test_locate() {
+        let home = Path::new("/Users/tcrypt");
+        let vars = |env: &'static str, xdg: &'static str| {
+            move |name: &str| match name {
+                CONFIG_ENV if !env.is_empty() => Some(OsString::from(env)),
+                "XDG_CONFIG_HOME" if !xdg.is_empty() => Some(OsString::from(xdg)),
+                _ => None,
+            }
+        };
+
+        let test_cases = [
+            (
+                Some("flag.toml"),
+                None,
+                vars("/env.toml", "/xdg"),
+                "flag.toml",
+                ConfigSource::Flag,
+            ),
+            (
+                None,
+                None,
+                vars("/env.toml", "/xdg"),
+                "/env.toml",
+                ConfigSource::Env,
+            ),
+            (
+                None,
+                Some("/Users/alice"),
+                vars("", "/xdg"),
+                "/xdg/promptpath/config.toml",
+                ConfigSource::XdgConfigHome,
+            ),
+            (
+                None,
+                None,
+                vars("", "relative"),
+                "/Users/tcrypt/.config/promptpath/config.toml",
+                ConfigSource::Default,
+            ),
+            (
+                None,
+                None,
+                vars("", ""),
+                "/Users/tcrypt/.config/promptpath/config.toml",
+                ConfigSource::Default,
+            ),
+            (
+                None,
+                Some("/Users/alice"),
+                vars("", ""),
+                "/Users/alice/.config/promptpath/config.toml",
+                ConfigSource::SudoUser,
+            ),
+        ];
+
+        for (flag, sudo_home, var, expected_path, expected_source) in test_cases {
+            let location = locate_with(flag.map(Path::new), home, sudo_home.map(Path::new), var);
+            assert_eq!(
+                location,
+                ConfigLocation {
+                    path: PathBuf::from(expected_path),
+                    source: expected_source,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_expand_vars() {
+        let var = |name: &str| match name {
+            "WORKSPACE" => Some(OsString::from("/work/space")),
+            "GOPATH" => Some(OsString::from("/Users/tcrypt/go")),
+            "EMPTY" => Some(OsString::new()),
+            "DOTS" => Some(OsString::from("a.b")),
+            _ => None,
+        };
+        let keep = |value: &str| value.to_string();
+
+        let test_cases = [
+            ("~/code/x", Ok("~/code/x")),
+            ("$WORKSPACE/api", Ok("/work/space/api")),
+            ("${GOPATH}/src/corp", Ok("/Users/tcrypt/go/src/corp")),
+            ("${GOPATH}src", Ok("/Users/tcrypt/gosrc")),
+            ("${CORP_ROOT:-~/corp}/api", Ok("~/corp/api")),
+            ("${EMPTY:-/fallback}", Ok("/fallback")),
+            ("/a$EMPTY/b", Ok("/a/b")),
+            ("/cost/$/x$", Ok("/cost/$/x$")),
+            ("$WORKSPACE_2/x", Err("WORKSPACE_2")),
+            ("${MISSING}/x", Err("MISSING")),
+        ];
+
+        for (input, expected) in test_cases {
+            let expected = match expected {
+                Ok(value) => Ok(value.to_string()),
+                Err(variable) => Err(MappingError::UnsetVariable {
+                    path: input.to_string(),
+                    variable: variable.to_string(),
+                }),
+            };
+            assert_eq!(
+                expand_vars(input, var, keep),
+                expected,
+                "Failed test: {}",
+                input
+            );
+        }
+
+        assert_eq!(
+            expand_vars("${WORKSPACE", var, keep),
+            Err(MappingError::UnterminatedVariable("${WORKSPACE".into()))
+        );
+        assert_eq!(
+            expand_vars("$DOTS/x", var, regex_lite::escape).unwrap(),
+            r"a\.b/x"
+        );
+    }
+
+    #[test]
+    fn test_expand_user() {
+        let user_home = |name: &str| match name {
+            "alice" => Some(PathBuf::from("/home/alice")),
+            "svc.bot" => Some(PathBuf::from("/srv/svc.bot")),
+            _ => None,
+        };
+        let keep = |value: &str| value.to_string();
+
+        let test_cases = [
+            ("~alice/code/x", Ok("/home/alice/code/x")),
+            ("~alice", Ok("/home/alice")),
+            ("~svc.bot/api", Ok("/srv/svc.bot/api")),
+            ("~/code/x", Ok("~/code/x")),
+            ("~", Ok("~")),
+            ("/home/alice/~bob", Ok("/home/alice/~bob")),
+            ("~bob/code", Err("bob")),
+        ];
+
+        for (input, expected) in test_cases {
+            let expected = match expected {
+                Ok(value) => Ok(value.to_string()),
+                Err(user) => Err(MappingError::UnknownUser {
+                    path: input.to_string(),
+                    user: user.to_string(),
+                }),
+            };
+            assert_eq!(
+                expand_user(input, user_home, keep),
+                expected,
+                "Failed test: {}",
+                input
+            );
+        }
+
+        assert_eq!(
+            expand_user("~svc.bot/(a|b)", user_home, regex_lite::escape),
+            Ok(String::from(r"/srv/svc\.bot/(a|b)"))
+        );
+    }
+
+    #[test]
+    fn test_line_column() {
+        let contents = "ab\ncdé\nf";
+        assert_eq!(line_column(contents, 0), (1, 1));
+        assert_eq!(line_column(contents, 1), (1, 2));
+        assert_eq!(line_column(contents, 3), (2, 1));
+        assert_eq!(line_column(contents, 7), (2, 4));
+        assert_eq!(line_column(contents, 8), (3, 1));
+        assert_eq!(line_column(contents, 100), (3, 2));
+    }
+
+    #[test]
+    fn test_create_cache_dir() {
+        use std::os::unix::fs::{chown, PermissionsExt};
+
+        let root = scratch_dir("cache-dir");
+        let mine = root.join("mine");
+        let shared = root.join("shared");
+        let theirs = root.join("theirs");
+        for dir in [&mine, &shared, &theirs] {
+            fs::create_dir(dir).unwrap();
+        }
+        fs::set_permissions(&shared, fs::Permissions::from_mode(0o1777)).unwrap();
+        // Only root can give a directory away, and only root could write in it afterwards
+        let is_root = passwd::effective_uid() == 0;
+        if is_root {
+            chown(&theirs, Some(65534), None).unwrap();
+            chown(&shared, Some(65534), None).unwrap();
+        }
+
+        let test_cases = [
+            (mine.join(".cache/promptpath"), true),
+            (mine.clone(), true),
+            (shared.join("promptpath"), true),
+            (theirs.join(".cache/promptpath"), !is_root),
+            (theirs.join("promptpath"), !is_root),
+        ];
+        for (dir, expected) in &test_cases {
+            let created = create_cache_dir(dir).is_ok();
+            assert_eq!(created, *expected, "Failed test: {:?}", dir);
+            assert_eq!(dir.exists(), *expected, "Failed test: {:?}", dir);
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::reload::LiveContext;
+use crate::shell::Escape;
+use crate::{get_display, passwd, AppContext};
+
+// Name of the socket in $XDG_RUNTIME_DIR
+const SOCKET_NAME: &str = "promptpath.sock";
+
+// Name of the file next to the socket that the running daemon holds locked
+const LOCK_NAME: &str = "promptpath.lock";
+
+// How long either side waits on the other before giving up. A client that gives up computes the
+// nicknames itself.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+// Requests and responses longer than this are cut off, so a confused peer can't use up memory
+const MAX_MESSAGE_LEN: u64 = 1 << 20;
+
+#[derive(Error, Debug)]
+pub enum DaemonError {
+    #[error("XDG_RUNTIME_DIR isn't set, so there's nowhere to put the socket")]
+    NoRuntimeDir,
+    #[error("a daemon is already running on {0}")]
+    AlreadyRunning(PathBuf),
+    #[error("can't listen on {path}: {source}")]
+    Listen { path: PathBuf, source: io::Error },
+}
+
+// Returns the path of the daemon's socket, or None if XDG_RUNTIME_DIR isn't set. That directory
+// is only accessible to its user, so nobody else can connect.
+pub fn socket_path() -> Option<PathBuf> {
+    let dir = PathBuf::from(env::var_os("XDG_RUNTIME_DIR")?);
+    dir.is_absolute().then(|| dir.join(SOCKET_NAME))
+}
+
+// A socket being listened on, along with the lock that makes this the only daemon using it
+pub struct Listener {
+    listener: UnixListener,
+    _lock: fs::File,
+}
+
+// Answers queries on listener with the context in live until the process is killed
+pub fn serve(live: &mut LiveContext, listener: Listener) {
+    // A client that hangs up or sends garbage only loses its own answer
+    for stream in listener.listener.incoming().flatten() {
+        let _ = answer(live.get(), stream);
+    }
+}
+
+// Starts listening on socket, failing if another daemon is already running. Daemons started by
+// several shells at once take turns on a lock next to the socket, so only one of them replaces
+// the socket and the rest find it running, rather than each binding a socket of its own.
+pub fn listen(socket: &Path) -> Result<Listener, DaemonError> {
+    let listen_error = |source| DaemonError::Listen {
+        path: socket.to_path_buf(),
+        source,
+    };
+    let lock = fs::File::options()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(socket.with_file_name(LOCK_NAME))
+        .map_err(listen_error)?;
+    // SAFETY: the descriptor belongs to lock, which is open
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::WouldBlock {
+            return Err(DaemonError::AlreadyRunning(socket.to_path_buf()));
+        }
+        return Err(listen_error(err));
+    }
+
+    // Whatever socket is there was left behind by a daemon that's gone
+    let _ = fs::remove_file(socket);
+    let listener = UnixListener::bind(socket).map_err(listen_error)?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600)).map_err(listen_error)?;
+    Ok(Listener {
+        listener,
+        _lock: lock,
+    })
+}
+
+// Reads a request from stream and writes back the nickname of each path in it. A request is the
+// absolute path of the config file the client would load, the name of the escape to use, or
+// nothing for none, and then absolute paths, each field ending with a NUL. The response has a NUL
+// terminated nickname for each path, or is empty if the daemon loaded a different config.
+fn answer(ctx: &AppContext, mut stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut request = Vec::new();
+    (&mut stream)
+        .take(MAX_MESSAGE_LEN)
+        .read_to_end(&mut request)?;
+    let fields = split_fields(&request).ok_or_else(invalid_message)?;
+    let [config_path, escape, paths @ ..] = fields.as_slice() else {
+        return Err(invalid_message());
+    };
+    if *config_path != ctx.config_path.as_os_str().as_bytes() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the client wants another config",
+        ));
+    }
+    let escape = match *escape {
+        b"" => None,
+        name => Some(
+            std::str::from_utf8(name)
+                .ok()
+                .and_then(Escape::from_name)
+                .ok_or_else(invalid_message)?,
+        ),
+    };
+
+    let mut response = Vec::new();
+    for path in paths {
+        let path = Path::new(OsStr::from_bytes(path));
+        if !path.is_absolute() {
+            return Err(invalid_message());
+        }
+        response.extend_from_slice(get_display(ctx, path.to_path_buf(), escape).as_bytes());
+        response.push(0);
+    }
+    stream.write_all(&response)
+}
+
+// Asks the daemon listening on socket for the nicknames of paths, which must be absolute, as given
+// by the config at config_path. Fails if the daemon loaded a different config.
+pub fn query(
+    socket: &Path,
+    config_path: &Path,
+    escape: Option<Escape>,
+    paths: &[PathBuf],
+) -> io::Result<Vec<String>> {
+    // Only trust a socket that belongs to this user
+    if fs::symlink_metadata(socket)?.uid() != passwd::effective_uid() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the socket belongs to another user",
+        ));
+    }
+
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut request = Vec::new();
+    request.extend_from_slice(config_path.as_os_str().as_bytes());
+    request.push(0);
+    request.extend_from_slice(escape.map_or("", Escape::name).as_bytes());
+    request.push(0);
+    for path in paths {
+        request.extend_from_slice(path.as_os_str().as_bytes());
+        request.push(0);
+    }
+    stream.write_all(&request)?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut response = Vec::new();
+    (&mut stream)
+        .take(MAX_MESSAGE_LEN)
+        .read_to_end(&mut response)?;
+    let displays = split_fields(&response)
+        .filter(|fields| fields.len() == paths.len())
+        .ok_or_else(invalid_message)?;
+    displays
+        .into_iter()
+        .map(|display| String::from_utf8(display.to_vec()).map_err(|_| invalid_message()))
+        .collect()
+}
+
+// Splits a message into its NUL terminated fields, or returns None if the last one isn't
+// terminated, as happens when a message is cut off
+fn split_fields(message: &[u8]) -> Option<Vec<&[u8]>> {
+    match message.strip_suffix(b"\0") {
+        Some(message) => Some(message.split(|&byte| byte == 0).collect()),
+        None if message.is_empty() => Some(Vec::new()),
+        None => None,
+    }
+}
+
+fn invalid_message() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed message")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{scratch_dir, setup_test_context};
+    use std::thread;
+
+    #[test]
+    fn test_split_fields() {
+        let test_cases = [
+            ("", Some(vec![])),
+            ("\0", Some(vec![""])),
+            ("zsh\0/tmp\0", Some(vec!["zsh", "/tmp"])),
+            ("\0/a\0\0", Some(vec!["", "/a", ""])),
+            ("zsh\0/tm", None),
+        ];
+
+        for (input, expected) in test_cases {
+            let expected: Option<Vec<&[u8]>> =
+                expected.map(|fields| fields.into_iter().map(str::as_bytes).collect());
+            assert_eq!(
+                split_fields(input.as_bytes()),
+                expected,
+                "Failed test: {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_query() {
+        let dir = scratch_dir("daemon");
+        let socket = dir.join(SOCKET_NAME);
+        let listener = listen(&socket).unwrap();
+        assert!(matches!(
+            listen(&socket),
+            Err(DaemonError::AlreadyRunning(_))
+        ));
+
+        let config = Path::new("/etc/promptpath.toml");
+        let server = thread::spawn(move || {
+            // Taken whole, so the lock is released along with the socket
+            let listener = listener;
+            let mut ctx = setup_test_context();
+            ctx.config_path = config.to_path_buf();
+            for stream in listener.listener.incoming().take(4) {
+                let _ = answer(&ctx, stream.unwrap());
+            }
+        });
+
+        let paths = [
+            PathBuf::from("/Users/tcrypt/code/github.com/tyler-smith/promptpath/src"),
+            PathBuf::from("/Users/tcrypt/100%"),
+        ];
+        assert_eq!(
+            query(&socket, config, None, &paths).unwrap(),
+            vec!["promptpath/src", "~/100%"]
+        );
+        assert_eq!(
+            query(&socket, config, Some(Escape::Zsh), &paths).unwrap(),
+            vec!["promptpath/src", "~/100%%"]
+        );
+        assert_eq!(
+            query(&socket, config, None, &[PathBuf::from("relative")])
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        // A client wanting another config gets nothing, and works the nicknames out itself
+        assert_eq!(
+            query(&socket, Path::new("/tmp/other.toml"), None, &paths)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        server.join().unwrap();
+
+        // A socket left behind by a daemon that's gone is replaced
+        listen(&socket).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
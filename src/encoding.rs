@@ -0,0 +1,64 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::os::unix::ffi::OsStrExt;
+
+use crate::config::{InvalidUtf8Config, InvalidUtf8Mode};
+
+// Converts a path or path component to text for display. Valid UTF-8 is borrowed as is, while
+// invalid byte sequences are escaped as \xNN or replaced, depending on config.
+pub fn render<'a>(text: &'a OsStr, config: &InvalidUtf8Config) -> Cow<'a, str> {
+    let bytes = text.as_bytes();
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut rendered = String::with_capacity(bytes.len() + 8);
+    for chunk in bytes.utf8_chunks() {
+        rendered.push_str(chunk.valid());
+        if chunk.invalid().is_empty() {
+            continue;
+        }
+        match config.mode {
+            InvalidUtf8Mode::Escape => {
+                for byte in chunk.invalid() {
+                    let _ = write!(rendered, "\\x{:02x}", byte);
+                }
+            }
+            InvalidUtf8Mode::Replace => rendered.push_str(&config.replacement),
+        }
+    }
+    Cow::Owned(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let test_cases: [(&[u8], InvalidUtf8Mode, &str); 7] = [
+            (b"promptpath", InvalidUtf8Mode::Escape, "promptpath"),
+            ("straße".as_bytes(), InvalidUtf8Mode::Escape, "straße"),
+            (b"caf\xe9", InvalidUtf8Mode::Escape, r"caf\xe9"),
+            (b"\xff\xfe/x", InvalidUtf8Mode::Escape, r"\xff\xfe/x"),
+            (b"\xe2\x82-", InvalidUtf8Mode::Escape, r"\xe2\x82-"),
+            (b"caf\xe9/\xff\xfe", InvalidUtf8Mode::Replace, "caf?/??"),
+            // An incomplete multibyte sequence is replaced once
+            (b"\xe2\x82-", InvalidUtf8Mode::Replace, "?-"),
+        ];
+
+        for (input, mode, expected) in test_cases {
+            let config = InvalidUtf8Config {
+                mode,
+                replacement: String::from("?"),
+            };
+            assert_eq!(
+                render(OsStr::from_bytes(input), &config),
+                expected,
+                "Failed test: {:?}",
+                input
+            );
+        }
+    }
+}
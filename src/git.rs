@@ -0,0 +1,242 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Finds the closest ancestor of path (including path itself) that contains a .git directory or
+// file. The search stops before reaching home or the root directory so a dotfiles repository in
+// home doesn't swallow every path beneath it.
+pub fn find_repo_root<'a>(home: Option<&Path>, path: &'a Path) -> Option<&'a Path> {
+    path.ancestors()
+        .take_while(|dir| Some(*dir) != home && dir.parent().is_some())
+        .find(|dir| dir.join(".git").exists())
+}
+
+// What HEAD of a repository points at
+#[derive(Debug, PartialEq)]
+pub enum Head {
+    // HEAD is a symbolic ref to a branch, which may not have any commits yet
+    Branch(String),
+    // HEAD points directly at a commit. The name is a tag pointing at the commit if one was
+    // found, otherwise the abbreviated commit hash.
+    Detached(String),
+}
+
+const SHORT_HASH_LEN: usize = 7;
+
+// Reads HEAD of the repository containing path directly from the files in the git directory.
+// Unlike the repository alias this searches all the way up to the root directory, since a branch
+// is just as meaningful for a repository in home as anywhere else.
+pub fn read_head(path: &Path) -> Option<Head> {
+    let work_tree = path.ancestors().find(|dir| dir.join(".git").exists())?;
+    let git_dir = resolve_git_dir(&work_tree.join(".git"))?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+
+    if let Some(reference) = head.strip_prefix("ref:") {
+        let reference = reference.trim();
+        let branch = reference.strip_prefix("refs/heads/").unwrap_or(reference);
+        return Some(Head::Branch(branch.to_string()));
+    }
+
+    if !is_object_id(head) {
+        return None;
+    }
+    let common_dir = resolve_common_dir(&git_dir);
+    let name = find_tag(&common_dir, head).unwrap_or_else(|| head[..SHORT_HASH_LEN].to_string());
+    Some(Head::Detached(name))
+}
+
+// Resolves the git directory for a .git entry. Worktrees and submodules use a file containing
+// "gitdir: <path>" instead of a directory, where the path may be relative to the file.
+fn resolve_git_dir(dot_git: &Path) -> Option<PathBuf> {
+    if dot_git.is_dir() {
+        return Some(dot_git.to_path_buf());
+    }
+
+    let contents = fs::read_to_string(dot_git).ok()?;
+    let target = contents.trim().strip_prefix("gitdir:")?.trim();
+    Some(dot_git.parent()?.join(target))
+}
+
+// Resolves the directory holding refs shared between worktrees. Linked worktrees have a
+// "commondir" file pointing back at the main repository's git directory.
+fn resolve_common_dir(git_dir: &Path) -> PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(contents) => git_dir.join(contents.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+// Loose tags beyond this many aren't read, since reading each one on every prompt would make
+// prompts slow in repositories with thousands of them. git gc packs them into packed-refs, which
+// is read in one go.
+const MAX_LOOSE_TAGS: usize = 256;
+
+// Finds a tag pointing at the commit, checking loose tags first and then packed-refs. Annotated
+// loose tags point at a tag object rather than the commit, and resolving them would require
+// reading the object database, so only packed-refs' peeled entries can match annotated tags. When
+// there are more than MAX_LOOSE_TAGS loose tags, none of them are checked.
+fn find_tag(common_dir: &Path, commit: &str) -> Option<String> {
+    let mut tags = Vec::new();
+    let loose_tag = if list_loose_tags(&common_dir.join("refs/tags"), "", &mut tags) {
+        find_loose_tag(common_dir, tags, commit)
+    } else {
+        None
+    };
+    loose_tag.or_else(|| find_packed_tag(common_dir, commit))
+}
+
+// Adds the names of the loose tags in dir to tags, prefixed by prefix. Returns false once there
+// are more than MAX_LOOSE_TAGS, without listing the rest.
+fn list_loose_tags(dir: &Path, prefix: &str, tags: &mut Vec<String>) -> bool {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return true;
+    };
+    for entry in entries.flatten() {
+        let tag = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            if !list_loose_tags(&entry.path(), &format!("{}/", tag), tags) {
+                return false;
+            }
+        } else {
+            tags.push(tag);
+            if tags.len() > MAX_LOOSE_TAGS {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn find_loose_tag(common_dir: &Path, mut tags: Vec<String>, commit: &str) -> Option<String> {
+    // Directory order is arbitrary, so sort to keep the choice stable between prompts
+    tags.sort();
+    let tags_dir = common_dir.join("refs/tags");
+    tags.into_iter().find(|tag| {
+        fs::read_to_string(tags_dir.join(tag)).is_ok_and(|target| target.trim() == commit)
+    })
+}
+
+fn find_packed_tag(common_dir: &Path, commit: &str) -> Option<String> {
+    let packed = fs::read_to_string(common_dir.join("packed-refs")).ok()?;
+    let mut names = Vec::new();
+    let mut last_tag: Option<&str> = None;
+
+    for line in packed.lines() {
+        // A ^ line holds the commit an annotated tag on the previous line peels to
+        if let Some(peeled) = line.strip_prefix('^') {
+            if peeled == commit {
+                names.extend(last_tag);
+            }
+            continue;
+        }
+
+        last_tag = None;
+        let Some((target, reference)) = line.split_once(' ') else {
+            continue;
+        };
+        if let Some(tag) = reference.strip_prefix("refs/tags/") {
+            if target == commit {
+                names.push(tag);
+            }
+            last_tag = Some(tag);
+        }
+    }
+
+    names.sort();
+    names.first().map(|name| name.to_string())
+}
+
+// Returns true for a full SHA-1 or SHA-256 object id
+fn is_object_id(s: &str) -> bool {
+    (s.len() == 40 || s.len() == 64) && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::scratch_dir;
+
+    const COMMIT: &str = "8f1a0b6c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a";
+    const TAG_OBJECT: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn test_read_head() {
+        let root = scratch_dir("git-head");
+        let repo = root.join("repo");
+        let git_dir = repo.join(".git");
+        fs::create_dir_all(git_dir.join("refs/tags/release")).unwrap();
+        fs::create_dir_all(repo.join("src")).unwrap();
+
+        // Branch, from the repository root and from a subdirectory
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        assert_eq!(read_head(&repo), Some(Head::Branch("feature/x".into())));
+        assert_eq!(
+            read_head(&repo.join("src")),
+            Some(Head::Branch("feature/x".into()))
+        );
+
+        // Detached without any tags
+        fs::write(git_dir.join("HEAD"), format!("{}\n", COMMIT)).unwrap();
+        assert_eq!(read_head(&repo), Some(Head::Detached("8f1a0b6".into())));
+
+        // Detached at an annotated tag in packed-refs
+        let packed = format!(
+            "# pack-refs with: peeled fully-peeled sorted\n\
+             {COMMIT} refs/heads/main\n\
+             {TAG_OBJECT} refs/tags/v1.0.0\n\
+             ^{COMMIT}\n"
+        );
+        fs::write(git_dir.join("packed-refs"), packed).unwrap();
+        assert_eq!(read_head(&repo), Some(Head::Detached("v1.0.0".into())));
+
+        // Loose tags take precedence over packed-refs
+        fs::write(
+            git_dir.join("refs/tags/release/v1"),
+            format!("{}\n", COMMIT),
+        )
+        .unwrap();
+        assert_eq!(read_head(&repo), Some(Head::Detached("release/v1".into())));
+
+        // Too many loose tags to read, so only packed-refs is checked
+        for i in 0..MAX_LOOSE_TAGS {
+            fs::write(git_dir.join(format!("refs/tags/t{}", i)), TAG_OBJECT).unwrap();
+        }
+        assert_eq!(read_head(&repo), Some(Head::Detached("v1.0.0".into())));
+
+        // Garbage in HEAD
+        fs::write(git_dir.join("HEAD"), "not a ref\n").unwrap();
+        assert_eq!(read_head(&repo), None);
+
+        // Outside of any repository
+        assert_eq!(read_head(&root), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_read_head_worktree() {
+        let root = scratch_dir("git-worktree");
+        let common_dir = root.join("repo/.git");
+        let worktree_git_dir = common_dir.join("worktrees/wt");
+        let worktree = root.join("wt");
+        fs::create_dir_all(&worktree_git_dir).unwrap();
+        fs::create_dir_all(&worktree).unwrap();
+        fs::write(common_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(
+            common_dir.join("packed-refs"),
+            format!("{COMMIT} refs/tags/v2\n"),
+        )
+        .unwrap();
+        fs::write(worktree_git_dir.join("commondir"), "../..\n").unwrap();
+        fs::write(worktree.join(".git"), "gitdir: ../repo/.git/worktrees/wt\n").unwrap();
+
+        fs::write(worktree_git_dir.join("HEAD"), "ref: refs/heads/fix\n").unwrap();
+        assert_eq!(read_head(&worktree), Some(Head::Branch("fix".into())));
+
+        // Tags are read from the main repository's git directory
+        fs::write(worktree_git_dir.join("HEAD"), COMMIT).unwrap();
+        assert_eq!(read_head(&worktree), Some(Head::Detached("v2".into())));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
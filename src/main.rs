@@ -1,90 +1,546 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use config::{
+    AbbreviateConfig, AbbreviateMode, CodeRootMapping, Config, ConfigLocation, ConfigSource,
+    GitConfig, InvalidUtf8Config, MappingError, MappingKind, PathsConfig, ProjectMapping,
+};
+use pattern::ProjectPattern;
+use reload::LiveContext;
+use shell::Escape;
 use std::env;
+use std::ffi::{CString, OsString};
+use std::fmt;
 use std::fs;
-use std::path::{Path, PathBuf};
-use thiserror::Error;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{self, Component, Path, PathBuf};
+use std::process;
+use trie::PathTrie;
+
+mod abbrev;
+mod batch;
+mod bench;
+mod cache;
+mod cli;
+mod config;
+mod daemon;
+mod encoding;
+mod git;
+mod passwd;
+mod pattern;
+mod reload;
+mod shell;
+mod trie;
 
-const CONFIG_PATH: &str = ".config/promptpath/config.toml";
-const CODE_ROOT: &str = "~/code";
 const UNKNOWN: &str = "unknown";
 
-#[derive(Error, Debug)]
-pub enum ConfigError {
-    #[error("Failed to read config file: {0}")]
-    FileRead(#[from] std::io::Error),
-    #[error("Failed to parse config file: {0}")]
-    ParseError(#[from] toml::de::Error),
+// A code root's normalized absolute path along with the label to display in its place
+#[derive(Debug)]
+struct CodeRoot {
+    path: PathBuf,
+    label: Option<String>,
 }
 
-#[derive(Deserialize, Default, Debug)]
-struct Config {
-    #[serde(default)]
-    projects: Vec<ProjectMapping>,
+// Literal project mappings, keyed by normalized absolute path, holding the alias
+type ProjectMappings = PathTrie<String>;
+
+#[derive(Debug)]
+struct AppContext {
+    // The config file the context was loaded from, which may not exist
+    config_path: PathBuf,
+    // None if the home directory couldn't be found, in which case nothing collapses to ~
+    home: Option<PathBuf>,
+    // None if home doesn't exist, in which case only paths spelled with home collapse to ~
+    home_identity: Option<HomeIdentity>,
+    project_mappings: ProjectMappings,
+    project_patterns: Vec<ProjectPattern>,
+    code_roots: Vec<CodeRoot>,
+    git: GitConfig,
+    abbreviate: AbbreviateConfig,
+    invalid_utf8: InvalidUtf8Config,
+    paths: PathsConfig,
+    // Under sudo, the effective and invoking users other than the one whose home is ~. Their homes
+    // always collapse to ~user.
+    sudo_users: Vec<passwd::User>,
+    // Shown after every path when running as root, empty otherwise
+    root_marker: String,
+}
+
+// Other ways of recognizing the home directory than by its path, so routes to it through a
+// symlinked home root like Silverblue's /home -> /var/home, or through a bind mount, still collapse
+// to ~
+#[derive(Debug)]
+struct HomeIdentity {
+    // home with its symlinks resolved, or None if that's home itself
+    canonical: Option<PathBuf>,
+    dev: u64,
+    ino: u64,
+}
+
+impl HomeIdentity {
+    fn new(home: &Path) -> Option<Self> {
+        let metadata = fs::metadata(home).ok()?;
+        let canonical = fs::canonicalize(home)
+            .ok()
+            .filter(|canonical| canonical != home);
+        Some(Self {
+            canonical,
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        })
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct ProjectMapping {
-    path: String,
+// The rule that produced a nickname
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Rule {
+    Home,
+    Project,
+    Git,
+    Code,
+    None,
+}
+
+impl Rule {
+    fn name(self) -> &'static str {
+        match self {
+            Rule::Home => "home",
+            Rule::Project => "project",
+            Rule::Git => "git",
+            Rule::Code => "code",
+            Rule::None => "none",
+        }
+    }
+}
+
+// A nickname split into the alias it starts with, which is always shown verbatim, and the path
+// below it. The alias is ~ or / for paths that didn't match any project, repository or code root.
+#[derive(Debug, PartialEq)]
+struct Nickname {
+    rule: Rule,
     alias: String,
+    subpath: String,
 }
 
-#[derive(Debug)]
-struct AppContext {
-    home: PathBuf,
-    project_mappings: HashMap<PathBuf, (String, String)>,
+impl Nickname {
+    fn new(rule: Rule, alias: impl Into<String>, subpath: &str) -> Self {
+        Self {
+            rule,
+            alias: alias.into(),
+            subpath: subpath.trim_matches('/').to_string(),
+        }
+    }
+
+    // Splits a path such as ~/data or /usr/bin into its leading ~ or / and the rest of the path
+    fn from_path_str(path: &str) -> Self {
+        if path == "~" {
+            return Self::new(Rule::Home, "~", "");
+        }
+        if let Some(subpath) = path.strip_prefix("~/") {
+            return Self::new(Rule::Home, "~", subpath);
+        }
+        if path.starts_with('/') {
+            return Self::new(Rule::None, "/", path);
+        }
+        Self::new(Rule::None, "", path)
+    }
+}
+
+impl fmt::Display for Nickname {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.alias)?;
+        if !self.alias.is_empty() && !self.alias.ends_with('/') && !self.subpath.is_empty() {
+            f.write_str("/")?;
+        }
+        f.write_str(&self.subpath)
+    }
 }
 
 impl AppContext {
-    fn new() -> Self {
-        let home = env::var_os("HOME")
-            .map(PathBuf::from)
-            .expect("HOME environment variable must be set");
+    fn new(config_flag: Option<&Path>) -> Self {
+        let (ctx, err) = Self::load(config_flag);
+        // A broken config silently drops every alias so make some noise. Mappings skipped for a
+        // variable or user that's missing are left to check and --explain, since with shared
+        // dotfiles that's expected on some machines.
+        match err {
+            Some(config::ConfigError::InvalidMapping { source, .. })
+                if source.depends_on_environment() => {}
+            Some(err) => config::warn_throttled(ctx.home.as_deref(), &err),
+            None => {}
+        }
+        ctx
+    }
+
+    // Loads the config file, returning the context along with the first problem with it. A file
+    // that can't be read or parsed gives the defaults, and mappings that can't be used are skipped.
+    fn load(config_flag: Option<&Path>) -> (Self, Option<config::ConfigError>) {
+        let home = passwd::home_dir();
+        let sudo_user = passwd::sudo_user();
 
         // Load project mappings from the config file
-        let config = Self::load_config(&home).unwrap_or_default();
-        let project_mappings = config
-            .projects
+        let cache_dir = config::cache_dir(home.as_deref());
+        let (location, config_home) =
+            locate_config(config_flag, home.as_deref(), sudo_user.as_ref());
+        let mut problem = None;
+        let expanded = cache::load(
+            cache_dir.as_deref(),
+            &location.path,
+            config_home,
+            |config, lookups| {
+                let var = |name: &str| lookups.var(name);
+                let user_home = |name: &str| lookups.user_home(name);
+                expand_config(config_home, config, var, user_home)
+            },
+        );
+        let expanded = match expanded {
+            Ok(expanded) => expanded,
+            Err(err) => {
+                // Without a config file the defaults are exactly what's wanted, unless a file was
+                // asked for by name
+                if !err.is_missing() || location.is_explicit() {
+                    problem = Some(err);
+                }
+                let var = |name: &str| env::var_os(name);
+                let user_home = |name: &str| passwd::by_name(name).map(|user| user.home);
+                expand_config(config_home, Config::default(), var, user_home)
+            }
+        };
+        let config = expanded.config;
+        let (mut project_mappings, project_patterns, mut errors) =
+            compile_project_mappings(config_home, expanded.projects);
+        let (mut code_roots, code_root_errors) = split_errors(expanded.code_roots);
+        errors.extend(code_root_errors);
+        // Mistakes in the config itself come first, as they're the ones worth warning about
+        if let Some(source) = errors
             .into_iter()
-            .map(|mapping| {
-                let key = expand_home_alias(&home, &mapping.path);
-                (key, (mapping.path, mapping.alias))
-            })
-            .collect();
+            .min_by_key(MappingError::depends_on_environment)
+        {
+            problem.get_or_insert(config::ConfigError::InvalidMapping {
+                path: location.path.clone(),
+                source,
+            });
+        }
 
-        Self {
+        let home_identity = home.as_deref().and_then(HomeIdentity::new);
+        if let (Some(home), Some(identity)) = (&home, &home_identity) {
+            rebase_config_paths(home, identity, &mut project_mappings, &mut code_roots);
+        }
+
+        let mut sudo_users = Vec::new();
+        if let Some(sudo_user) = sudo_user {
+            sudo_users.extend(passwd::by_uid(passwd::effective_uid()));
+            sudo_users.push(sudo_user);
+        }
+        // A home of / would swallow every path, as it's the home of root on some systems
+        sudo_users.retain(|user| Some(&user.home) != home.as_ref() && user.home != Path::new("/"));
+        let root_marker = if passwd::effective_uid() == 0 {
+            config.sudo.root_marker
+        } else {
+            String::new()
+        };
+
+        let ctx = Self {
+            config_path: location.path,
             home,
+            home_identity,
             project_mappings,
+            project_patterns,
+            code_roots,
+            git: config.git,
+            abbreviate: config.abbreviate,
+            invalid_utf8: config.invalid_utf8,
+            paths: config.paths,
+            sudo_users,
+            root_marker,
+        };
+        (ctx, problem)
+    }
+}
+
+// Finds the config file, returning it along with the directory ~ stands for in it. Under sudo
+// that's the invoking user's home when their config is the one found.
+fn locate_config<'a>(
+    config_flag: Option<&Path>,
+    home: Option<&'a Path>,
+    sudo_user: Option<&'a passwd::User>,
+) -> (ConfigLocation, &'a Path) {
+    let home = config_home(home);
+    let sudo_home = sudo_user.map(|user| user.home.as_path());
+    let location = config::locate(config_flag, home, sudo_home);
+    match sudo_home {
+        Some(sudo_home) if location.source == ConfigSource::SudoUser => (location, sudo_home),
+        _ => (location, home),
+    }
+}
+
+// Returns the directory ~ stands for in the config and that the config is found relative to. Without
+// a home directory that's /, so a config can still be given with --config or the environment.
+fn config_home(home: Option<&Path>) -> &Path {
+    home.unwrap_or(Path::new("/"))
+}
+
+// Expands environment variables in a path from the config with var, and then a leading ~user
+// with user_home
+fn expand_config_path(
+    path: &str,
+    var: impl Fn(&str) -> Option<OsString>,
+    user_home: impl Fn(&str) -> Option<PathBuf>,
+    escape: impl Fn(&str) -> String + Copy,
+) -> Result<String, MappingError> {
+    let path = config::expand_vars(path, var, escape)?;
+    config::expand_user(&path, user_home, escape)
+}
+
+// A project mapping with environment variables and ~user expanded in its path. Literal paths are
+// normalized too, as they're used as they are.
+#[derive(Debug)]
+enum ExpandedMapping {
+    Literal { key: PathBuf, alias: String },
+    Pattern(ProjectMapping),
+}
+
+// A config with the paths of its mappings expanded, which is the form the cache keeps so they
+// aren't expanded again for every prompt. Mappings that couldn't be expanded keep their error in
+// their place, so problems come out in the order they're written.
+#[derive(Debug)]
+struct ExpandedConfig {
+    // The rest of the config, with no projects or code roots
+    config: Config,
+    projects: Vec<Result<ExpandedMapping, MappingError>>,
+    code_roots: Vec<Result<CodeRoot, MappingError>>,
+}
+
+fn expand_config(
+    home: &Path,
+    mut config: Config,
+    var: impl Fn(&str) -> Option<OsString> + Copy,
+    user_home: impl Fn(&str) -> Option<PathBuf> + Copy,
+) -> ExpandedConfig {
+    let projects = mem::take(&mut config.projects);
+    let code_roots = mem::take(&mut config.code_roots);
+    ExpandedConfig {
+        projects: expand_project_mappings(home, projects, var, user_home),
+        code_roots: expand_code_roots(home, code_roots, var, user_home),
+        config,
+    }
+}
+
+// Expands environment variables and ~user in the paths of project mappings, and normalizes
+// literal paths into absolute ones. Mappings using unset variables or unknown users give errors.
+fn expand_project_mappings(
+    home: &Path,
+    projects: Vec<ProjectMapping>,
+    var: impl Fn(&str) -> Option<OsString> + Copy,
+    user_home: impl Fn(&str) -> Option<PathBuf> + Copy,
+) -> Vec<Result<ExpandedMapping, MappingError>> {
+    projects
+        .into_iter()
+        .map(|mapping| {
+            let path = match mapping.kind {
+                MappingKind::Regex => {
+                    expand_config_path(&mapping.path, var, user_home, regex_lite::escape)?
+                }
+                _ => expand_config_path(&mapping.path, var, user_home, str::to_string)?,
+            };
+            Ok(match mapping.kind {
+                MappingKind::Literal => ExpandedMapping::Literal {
+                    key: normalize_config_path(home, &path),
+                    alias: mapping.alias,
+                },
+                _ => ExpandedMapping::Pattern(ProjectMapping { path, ..mapping }),
+            })
+        })
+        .collect()
+}
+
+// Splits expanded project mappings into literal paths, keyed by their absolute path, and compiled
+// glob and regex patterns. Mappings that couldn't be expanded or whose patterns fail to compile
+// are skipped and their errors returned.
+fn compile_project_mappings(
+    home: &Path,
+    projects: Vec<Result<ExpandedMapping, MappingError>>,
+) -> (ProjectMappings, Vec<ProjectPattern>, Vec<MappingError>) {
+    let mut mappings = ProjectMappings::new();
+    let mut patterns = Vec::new();
+    let mut errors = Vec::new();
+
+    for mapping in projects {
+        let mapping = match mapping {
+            Ok(ExpandedMapping::Literal { key, alias }) => {
+                mappings.insert(&key, alias);
+                continue;
+            }
+            Ok(ExpandedMapping::Pattern(mapping)) => mapping,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+
+        let pattern = match mapping.kind {
+            MappingKind::Regex => ProjectPattern::regex(home, &mapping.path, &mapping.alias),
+            _ => ProjectPattern::glob(home, &mapping.path, &mapping.alias),
+        };
+        match pattern {
+            Ok(pattern) => patterns.push(pattern),
+            Err(err) => errors.push(err.into()),
         }
     }
 
-    fn load_config(home: &Path) -> Result<Config, ConfigError> {
-        let config_path = home.join(CONFIG_PATH);
-        let contents = fs::read_to_string(&config_path)?;
-        Ok(toml::from_str(&contents)?)
+    (mappings, patterns, errors)
+}
+
+// Expands and compiles project mappings as the config gives them
+fn build_project_mappings(
+    home: &Path,
+    projects: Vec<ProjectMapping>,
+    var: impl Fn(&str) -> Option<OsString> + Copy,
+    user_home: impl Fn(&str) -> Option<PathBuf> + Copy,
+) -> (ProjectMappings, Vec<ProjectPattern>, Vec<MappingError>) {
+    let projects = expand_project_mappings(home, projects, var, user_home);
+    compile_project_mappings(home, projects)
+}
+
+// Normalizes code roots into absolute paths, so it doesn't matter whether they were written as
+// ~/src, /home/me/src or $HOME/src. Roots using unset variables or unknown users give errors.
+fn expand_code_roots(
+    home: &Path,
+    roots: Vec<CodeRootMapping>,
+    var: impl Fn(&str) -> Option<OsString> + Copy,
+    user_home: impl Fn(&str) -> Option<PathBuf> + Copy,
+) -> Vec<Result<CodeRoot, MappingError>> {
+    roots
+        .into_iter()
+        .map(|root| {
+            let path = expand_config_path(&root.path, var, user_home, str::to_string)?;
+            Ok(CodeRoot {
+                path: normalize_config_path(home, &path),
+                label: root.label,
+            })
+        })
+        .collect()
+}
+
+// Expands code roots as the config gives them, skipping those that can't be and returning their
+// errors
+fn build_code_roots(
+    home: &Path,
+    roots: Vec<CodeRootMapping>,
+    var: impl Fn(&str) -> Option<OsString> + Copy,
+    user_home: impl Fn(&str) -> Option<PathBuf> + Copy,
+) -> (Vec<CodeRoot>, Vec<MappingError>) {
+    split_errors(expand_code_roots(home, roots, var, user_home))
+}
+
+fn split_errors<T>(results: Vec<Result<T, MappingError>>) -> (Vec<T>, Vec<MappingError>) {
+    let mut values = Vec::new();
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok(value) => values.push(value),
+            Err(err) => errors.push(err),
+        }
+    }
+    (values, errors)
+}
+
+// Get the text to display for a given path: its nickname followed by the branch segment. With
+// escape, the nickname and branch name are escaped for a prompt but the branch formats from the
+// config are left alone so they can use the prompt's own escapes.
+fn get_display(ctx: &AppContext, path: PathBuf, escape: Option<Escape>) -> String {
+    let branch = get_branch_segment(ctx, &path, escape);
+    let mut display = escape_text(escape, &get_nickname(ctx, path));
+    display.push_str(&branch);
+    display.push_str(&ctx.root_marker);
+    display
+}
+
+// Get the branch segment for a given path, or an empty string if branches aren't shown or the
+// path isn't inside a repository
+fn get_branch_segment(ctx: &AppContext, path: &Path, escape: Option<Escape>) -> String {
+    if !ctx.git.branch {
+        return String::new();
+    }
+
+    match git::read_head(path) {
+        Some(git::Head::Branch(name)) => ctx
+            .git
+            .branch_format
+            .replace("{branch}", &escape_text(escape, &name)),
+        Some(git::Head::Detached(name)) => ctx
+            .git
+            .detached_format
+            .replace("{branch}", &escape_text(escape, &name)),
+        None => String::new(),
+    }
+}
+
+fn escape_text(escape: Option<Escape>, text: &str) -> String {
+    match escape {
+        Some(escape) => escape.escape(text),
+        None => text.to_string(),
     }
 }
 
 // Get the nickname for a given path
 fn get_nickname(ctx: &AppContext, path: PathBuf) -> String {
+    resolve_nickname(ctx, &path).to_string()
+}
+
+// Resolve the nickname for a given path, keeping track of which rule produced it
+fn resolve_nickname(ctx: &AppContext, path: &Path) -> Nickname {
+    let rebased = match (&ctx.home, &ctx.home_identity) {
+        (Some(home), Some(identity)) => rebase_on_home(home, identity, path),
+        _ => None,
+    };
+    // Mappings and code roots written with the route a path took to home match it as it is, and
+    // everything else matches it moved under home
+    let routes: Vec<&Path> = match rebased.as_deref() {
+        Some(rebased) => vec![path, rebased],
+        None => vec![path],
+    };
+    let path = routes[routes.len() - 1];
+
     // Special cases:
     //   If we're in the home directory, return ~
     //   If we're in the root directory, return /
-    if path == ctx.home {
-        return "~".to_string();
+    if ctx.home.as_deref() == Some(path) {
+        return Nickname::from_path_str("~");
+    }
+    if path == Path::new("/") {
+        return Nickname::from_path_str("/");
+    }
+
+    for route in &routes {
+        if let Some(nickname) = collapse_project_alias(ctx, route) {
+            return abbreviate(&ctx.abbreviate, &ctx.invalid_utf8, route, nickname);
+        }
     }
-    if path == PathBuf::from("/") {
-        return "/".to_string();
+    if let Some((physical, nickname)) = collapse_physical_project_alias(ctx, path) {
+        return abbreviate(&ctx.abbreviate, &ctx.invalid_utf8, &physical, nickname);
+    }
+    if let Some(nickname) = collapse_git_alias(ctx, path) {
+        return abbreviate(&ctx.abbreviate, &ctx.invalid_utf8, path, nickname);
+    }
+    for route in &routes {
+        if let Some(nickname) = collapse_code_alias(ctx, route) {
+            return abbreviate(&ctx.abbreviate, &ctx.invalid_utf8, route, nickname);
+        }
     }
 
-    let nickname = collapse_home_alias(&ctx.home, &path);
-    let nickname = collapse_project_alias(ctx, &path, nickname);
-    let nickname = collapse_code_alias(nickname);
-    strip_trailing_slashes(nickname)
+    // Paths outside every root are only collapsed to ~/...
+    let nickname = collapse_user_home_alias(ctx, path).unwrap_or_else(|| {
+        let path = collapse_home_alias(ctx.home.as_deref(), path, &ctx.invalid_utf8);
+        Nickname::from_path_str(&path)
+    });
+    abbreviate(&ctx.abbreviate, &ctx.invalid_utf8, path, nickname)
 }
 
-// Expands a path that starts with ~/ to an absolute path
+// Expands a path that is ~ or starts with ~/ to an absolute path
 fn expand_home_alias(home: &Path, path: &str) -> PathBuf {
+    if path == "~" {
+        return home.to_path_buf();
+    }
     if !path.starts_with("~/") {
         return PathBuf::from(path);
     }
@@ -94,96 +550,616 @@ fn expand_home_alias(home: &Path, path: &str) -> PathBuf {
     normalized
 }
 
-// Collapses a path that starts with the home directory to ~/...
-fn collapse_home_alias(home: &Path, path: &Path) -> String {
-    if !path.starts_with(home) {
-        return path.to_string_lossy().into_owned();
+// Turns a path written in the config into a normalized absolute path, so ~/code/x, /home/me/code/x,
+// ~/code/x/ and ~/code//y/../x all name the same directory. Relative paths are taken to be
+// relative to home.
+fn normalize_config_path(home: &Path, path: &str) -> PathBuf {
+    cli::normalize_path(home, &expand_home_alias(home, path))
+}
+
+// Joins the components of path below the first depth components, not counting the root
+fn subpath_after(path: &Path, depth: usize, invalid_utf8: &InvalidUtf8Config) -> String {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .skip(depth)
+        .map(|component| encoding::render(component.as_os_str(), invalid_utf8))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Moves mapping keys and code roots written with home's symlinks resolved under home, where
+// resolve_nickname moves paths too, so the two still meet. Only the resolved path is checked, as
+// looking for home by device and inode would stat every ancestor of every mapping on each prompt.
+fn rebase_config_paths(
+    home: &Path,
+    identity: &HomeIdentity,
+    project_mappings: &mut ProjectMappings,
+    code_roots: &mut [CodeRoot],
+) {
+    let Some(canonical) = identity.canonical.as_deref() else {
+        return;
+    };
+    // One inside the other can't be told apart by prefix
+    if home.starts_with(canonical) || canonical.starts_with(home) {
+        return;
+    }
+
+    project_mappings.move_prefix(canonical, home);
+    for root in code_roots {
+        if let Ok(subpath) = root.path.strip_prefix(canonical) {
+            root.path = home.join(subpath);
+        }
+    }
+}
+
+// Rewrites a path that reaches the home directory by some other route to start with home, keeping
+// the rest of the path as it is. Returns None if path already starts with home or isn't inside it.
+fn rebase_on_home(home: &Path, identity: &HomeIdentity, path: &Path) -> Option<PathBuf> {
+    if path.starts_with(home) {
+        return None;
     }
+    let canonical_subpath = identity
+        .canonical
+        .as_deref()
+        .and_then(|canonical| path.strip_prefix(canonical).ok());
+    let subpath = match canonical_subpath {
+        Some(subpath) => subpath,
+        None => {
+            // Fall back to comparing each ancestor with home by device and inode, which sees
+            // through bind mounts and NFS paths that canonicalize doesn't
+            let ancestor = path.ancestors().find(|dir| {
+                fs::metadata(dir).is_ok_and(|metadata| {
+                    metadata.dev() == identity.dev && metadata.ino() == identity.ino
+                })
+            })?;
+            path.strip_prefix(ancestor).ok()?
+        }
+    };
+
+    let mut rebased = home.to_path_buf();
+    rebased.extend(subpath);
+    Some(rebased)
+}
 
-    let mut result = String::with_capacity(path.as_os_str().len());
-    result.push('~');
-    result.push('/');
-    result.push_str(path.strip_prefix(home).unwrap().to_str().unwrap());
-    result
+// Collapses a path that starts with the home directory to ~/...
+fn collapse_home_alias(
+    home: Option<&Path>,
+    path: &Path,
+    invalid_utf8: &InvalidUtf8Config,
+) -> String {
+    match home.and_then(|home| path.strip_prefix(home).ok()) {
+        Some(subpath) => format!("~/{}", encoding::render(subpath.as_os_str(), invalid_utf8)),
+        None => encoding::render(path.as_os_str(), invalid_utf8).into_owned(),
+    }
 }
 
-// Finds the project that matches the path, or None if no match is found.
-// If multiple matches are found we take the one with the longest prefix.
-fn collapse_project_alias(ctx: &AppContext, path: &Path, path_str: String) -> String {
-    let longest_match = ctx
-        .project_mappings
+// Collapses a path inside a mapped project to the project's alias, or None if no match is found.
+// If multiple matches are found we take the one with the longest prefix. Literal mappings win
+// ties with patterns, and earlier patterns win ties with later ones.
+fn collapse_project_alias(ctx: &AppContext, path: &Path) -> Option<Nickname> {
+    let longest_match = ctx.project_mappings.longest_prefix(path);
+
+    let longest_pattern = ctx
+        .project_patterns
         .iter()
-        .filter(|(key, _)| path.starts_with(key))
-        .max_by_key(|(key, _)| key.as_os_str().len())
-        .map(|(_, (path, alias))| (path, alias));
+        .filter_map(|pattern| pattern.matches(path))
+        .rev()
+        .max_by_key(|found| found.depth);
 
-    match longest_match {
-        Some((project_path, alias)) => {
-            let shortened = path_str.replacen(project_path, alias, 1);
-            let shortened = shortened.trim_start_matches('/');
-            shortened.to_string()
+    // The nickname is rebuilt from the path's components below the match rather than by editing
+    // the path as a string, so it doesn't matter how the mapping was spelled
+    match (longest_match, longest_pattern) {
+        (Some((depth, _)), Some(found)) if found.depth > depth => {
+            let subpath = subpath_after(path, found.depth, &ctx.invalid_utf8);
+            Some(Nickname::new(Rule::Project, found.alias, &subpath))
         }
-        None => path_str,
+        (Some((depth, alias)), _) => {
+            let subpath = subpath_after(path, depth, &ctx.invalid_utf8);
+            Some(Nickname::new(Rule::Project, alias.as_str(), &subpath))
+        }
+        (None, Some(found)) => {
+            let subpath = subpath_after(path, found.depth, &ctx.invalid_utf8);
+            Some(Nickname::new(Rule::Project, found.alias, &subpath))
+        }
+        (None, None) => None,
     }
 }
 
-// Collapses a path that starts with the code directory to ~/code/...
-fn collapse_code_alias(path: String) -> String {
-    if path == CODE_ROOT {
-        return path[2..].to_string();
+// Collapses a path to a project alias by matching the project mappings against the path with its
+// symlinks resolved, for mappings written with a directory's real location. Returns the resolved
+// path along with the nickname, or None if matching physical paths is disabled or nothing matches.
+fn collapse_physical_project_alias(ctx: &AppContext, path: &Path) -> Option<(PathBuf, Nickname)> {
+    if !ctx.paths.match_physical {
+        return None;
     }
 
-    if !path.starts_with(CODE_ROOT) {
-        return path;
+    let physical = fs::canonicalize(path).ok()?;
+    if physical == path {
+        return None;
     }
+    let nickname = collapse_project_alias(ctx, &physical)?;
+    Some((physical, nickname))
+}
 
-    // Attempt to replace the code root with the alias. If no change happens we didn't find a match
-    // and we should return the original path. If the new path does not start with a / then return
-    // the original path.
-    let new_path = path.replacen(CODE_ROOT, "", 1);
-    if new_path.eq(&path) {
-        return path;
+// Collapses a path inside a git repository to <repo-dir-name>/<subpath>, or None if automatic
+// repository aliases are disabled or the path isn't inside a repository.
+fn collapse_git_alias(ctx: &AppContext, path: &Path) -> Option<Nickname> {
+    if !ctx.git.alias {
+        return None;
     }
-    if !new_path.starts_with('/') {
-        return path;
+
+    let repo_root = git::find_repo_root(ctx.home.as_deref(), path)?;
+    let repo_name = encoding::render(repo_root.file_name()?, &ctx.invalid_utf8);
+    let subpath = path.strip_prefix(repo_root).ok()?;
+    let subpath = encoding::render(subpath.as_os_str(), &ctx.invalid_utf8);
+
+    let alias = format!("{}{}", ctx.git.alias_prefix, repo_name);
+    Some(Nickname::new(Rule::Git, alias, &subpath))
+}
+
+// Collapses a path that starts with a code root to the path within that root, prefixed by the
+// root's label if it has one, or None if it's outside every root. If multiple roots match we take
+// the one with the longest prefix.
+fn collapse_code_alias(ctx: &AppContext, path: &Path) -> Option<Nickname> {
+    let root = ctx
+        .code_roots
+        .iter()
+        .filter(|root| path.starts_with(&root.path))
+        .max_by_key(|root| root.path.components().count())?;
+
+    // The code root itself is shown as its label, or as the root without its leading ~/ or /
+    let subpath = path.strip_prefix(&root.path).unwrap_or(path);
+    let alias = match &root.label {
+        Some(label) => label.clone(),
+        None if subpath.as_os_str().is_empty() => {
+            let root = collapse_home_alias(ctx.home.as_deref(), &root.path, &ctx.invalid_utf8);
+            root_display_name(&root).to_string()
+        }
+        None => String::new(),
+    };
+    let subpath = encoding::render(subpath.as_os_str(), &ctx.invalid_utf8);
+    Some(Nickname::new(Rule::Code, alias, &subpath))
+}
+
+// Collapses a path inside another user's home directory to ~user/..., or None if that's disabled,
+// the path is inside the current home or no user's home contains it. Users are looked up by the
+// names of the path's ancestors, so only homes named after their user are found, and system
+// accounts that can't log in are ignored so /bin doesn't become ~bin. Only an ancestor next to a
+// known home is looked up, such as /home/alice next to /home/tcrypt, since each lookup may ask a
+// directory server.
+fn collapse_user_home_alias(ctx: &AppContext, path: &Path) -> Option<Nickname> {
+    if ctx
+        .home
+        .as_deref()
+        .is_some_and(|home| path.starts_with(home))
+    {
+        return None;
     }
-    strip_leading_slashes(new_path)
+
+    // The users involved in sudo are known without searching, so they're collapsed even when
+    // other users' homes aren't. The deepest home wins if one is inside another.
+    let sudo_user = ctx
+        .sudo_users
+        .iter()
+        .filter(|user| path.starts_with(&user.home))
+        .max_by_key(|user| user.home.components().count())
+        .map(|user| (user.name.clone(), user.home.as_path()));
+    let (user, home) = match sudo_user {
+        Some(found) => found,
+        None if ctx.paths.user_homes => path.ancestors().find_map(|dir| {
+            let parent = dir.parent()?;
+            let next_to_home = |home: &Path| home.parent() == Some(parent);
+            if !(ctx.home.as_deref().is_some_and(next_to_home)
+                || ctx.sudo_users.iter().any(|user| next_to_home(&user.home)))
+            {
+                return None;
+            }
+            let user = passwd::by_name(dir.file_name()?.to_str()?)?;
+            (user.home == dir && user.can_log_in()).then_some((user.name, dir))
+        })?,
+        None => return None,
+    };
+    let subpath = path.strip_prefix(home).ok()?;
+    let subpath = encoding::render(subpath.as_os_str(), &ctx.invalid_utf8);
+    Some(Nickname::new(Rule::Home, format!("~{}", user), &subpath))
+}
+
+// Returns the name to show for a code root without a label
+fn root_display_name(root: &str) -> &str {
+    root.strip_prefix("~/")
+        .unwrap_or_else(|| root.trim_start_matches('/'))
 }
 
-// Strips all leading slashes from a path, except for the root directory
-fn strip_leading_slashes(path: String) -> String {
-    if path.eq("/") {
-        return path;
+// Abbreviates the path below the nickname's alias according to the configured mode
+fn abbreviate(
+    config: &AbbreviateConfig,
+    invalid_utf8: &InvalidUtf8Config,
+    path: &Path,
+    nickname: Nickname,
+) -> Nickname {
+    if nickname.subpath.is_empty() {
+        return nickname;
+    }
+
+    if config.mode == AbbreviateMode::None {
+        return nickname;
+    }
+
+    // The subpath is always the tail of the path, so the directory holding its first component is
+    // found by walking up once per component. Both modes work on the path's own components rather
+    // than the rendered subpath, so escapes in names that aren't valid UTF-8 aren't cut apart.
+    let depth = nickname.subpath.split('/').count();
+    let base = match path.ancestors().nth(depth) {
+        Some(base) => base,
+        None => return nickname,
+    };
+    let tail = path.strip_prefix(base).unwrap_or(path);
+    let subpath = match config.mode {
+        AbbreviateMode::None => return nickname,
+        AbbreviateMode::Fish => abbrev::fish(tail, config.keep_full, config.length, invalid_utf8),
+        AbbreviateMode::Unique => abbrev::unique(
+            base,
+            tail,
+            config.keep_full,
+            config.length,
+            config.max_dir_entries,
+            invalid_utf8,
+        ),
+    };
+    Nickname {
+        subpath,
+        ..nickname
     }
-    path.trim_start_matches('/').to_owned()
 }
 
-// Strips all trailing slashes from a path, except for the root directory
-fn strip_trailing_slashes(path: String) -> String {
-    if path.eq("/") {
-        return path;
+// Returns the current directory as the shell sees it, taken from $PWD when that names the same
+// directory, or with its symlinks resolved if physical is set or $PWD is missing or stale
+fn current_dir(physical: bool) -> io::Result<PathBuf> {
+    let cwd = env::current_dir()?;
+    if physical {
+        return Ok(cwd);
+    }
+
+    match env::var_os("PWD").map(PathBuf::from) {
+        Some(pwd) if is_logical_path(&pwd, &cwd) => Ok(pwd),
+        _ => Ok(cwd),
+    }
+}
+
+// Returns true if pwd is an absolute path without . or .. components that names the same directory
+// as cwd, the same check pwd -L makes before trusting $PWD
+fn is_logical_path(pwd: &Path, cwd: &Path) -> bool {
+    if !pwd.is_absolute() {
+        return false;
+    }
+    if pwd
+        .components()
+        .any(|component| matches!(component, Component::CurDir | Component::ParentDir))
+    {
+        return false;
+    }
+    if pwd == cwd {
+        return true;
+    }
+
+    match (fs::metadata(pwd), fs::metadata(cwd)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
     }
-    path.trim_end_matches('/').to_owned()
 }
 
 // Get the nickname for the current working directory
-fn get_cwd_nickname(ctx: &AppContext) -> String {
-    let cwd = match env::current_dir() {
-        Ok(cwd) => cwd,
-        Err(_) => return UNKNOWN.to_string(),
+fn get_cwd_nickname(ctx: &AppContext, physical: bool, escape: Option<Escape>) -> String {
+    match current_dir(physical).and_then(|cwd| check_searchable(Path::new(".")).map(|()| cwd)) {
+        Ok(cwd) => get_display(ctx, cwd, escape),
+        Err(err) => get_lost_cwd_display(ctx, env::var_os("PWD"), &err, escape),
+    }
+}
+
+// Returns an error if dir can't be searched. getcwd still succeeds on Linux once the permissions
+// of the current directory are taken away, so this is what notices.
+fn check_searchable(dir: &Path) -> io::Result<()> {
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+    if unsafe { libc::access(dir.as_ptr(), libc::X_OK) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// Get the text to display when the current directory can't be found, such as after it was deleted
+// or its permissions changed. The last path the shell knew, pwd, is shown with a marker for why it
+// couldn't be found.
+fn get_lost_cwd_display(
+    ctx: &AppContext,
+    pwd: Option<OsString>,
+    err: &io::Error,
+    escape: Option<Escape>,
+) -> String {
+    let marker = match err.kind() {
+        io::ErrorKind::NotFound => ctx.paths.deleted_marker.as_str(),
+        io::ErrorKind::PermissionDenied => ctx.paths.no_access_marker.as_str(),
+        _ => "",
+    };
+
+    let mut display = match pwd.map(PathBuf::from) {
+        Some(pwd) if pwd.is_absolute() => {
+            let pwd = cli::normalize_path(Path::new("/"), &pwd);
+            escape_text(escape, &get_nickname(ctx, pwd))
+        }
+        _ => UNKNOWN.to_string(),
+    };
+    display.push_str(marker);
+    display
+}
+
+// Get the nickname for each of the given paths, which may be relative to the current directory
+fn get_path_nicknames(
+    ctx: &AppContext,
+    paths: Vec<PathBuf>,
+    physical: bool,
+    escape: Option<Escape>,
+) -> Vec<String> {
+    let cwd = current_dir(physical).unwrap_or_default();
+    paths
+        .into_iter()
+        .map(|path| get_display(ctx, cli::normalize_path(&cwd, &path), escape))
+        .collect()
+}
+
+// Asks a running daemon for the nicknames of paths, or of the current directory if there are none.
+// Returns None if there's no daemon or it didn't answer, or if the current directory is gone or
+// can't be searched, which needs the last known nickname worked out here.
+fn query_daemon(
+    config_flag: Option<&Path>,
+    paths: &[PathBuf],
+    physical: bool,
+    escape: Option<Escape>,
+) -> Option<Vec<String>> {
+    let socket = daemon::socket_path()?;
+    let cwd = current_dir(physical).ok()?;
+    check_searchable(Path::new(".")).ok()?;
+    let paths = if paths.is_empty() {
+        vec![cwd]
+    } else {
+        paths
+            .iter()
+            .map(|path| cli::normalize_path(&cwd, path))
+            .collect()
+    };
+    // The daemon only answers for the config this process would load
+    let home = passwd::home_dir();
+    let sudo_user = passwd::sudo_user();
+    let (location, _) = locate_config(config_flag, home.as_deref(), sudo_user.as_ref());
+    let config_path = path::absolute(&location.path).ok()?;
+    daemon::query(&socket, &config_path, escape, &paths).ok()
+}
+
+// Loads the config and answers queries from clients on the daemon socket until killed. Returns the
+// exit code if it can't start.
+fn run_daemon(config_flag: Option<&Path>) -> i32 {
+    let Some(socket) = daemon::socket_path() else {
+        eprintln!("promptpath: {}", daemon::DaemonError::NoRuntimeDir);
+        return 1;
+    };
+    // Checked before loading the config, so a daemon started by every new shell exits quietly
+    // rather than warning about the config again
+    let listener = match daemon::listen(&socket) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("promptpath: {}", err);
+            return 1;
+        }
     };
-    get_nickname(ctx, cwd)
+    let mut live = LiveContext::new(config_flag);
+
+    // Shells started with the daemon in the background hang up when they exit, and the daemon
+    // shouldn't keep whatever directory it was started in busy
+    // SAFETY: ignoring a signal has no preconditions
+    unsafe { libc::signal(libc::SIGHUP, libc::SIG_IGN) };
+    let _ = env::set_current_dir("/");
+
+    daemon::serve(&mut live, listener);
+    0
+}
+
+// Checks that the config file parses, printing a summary or the error. Returns the exit code.
+fn run_check(config_flag: Option<&Path>) -> i32 {
+    let home = passwd::home_dir();
+    let sudo_user = passwd::sudo_user();
+    let (location, home) = locate_config(config_flag, home.as_deref(), sudo_user.as_ref());
+
+    match describe_config(home, &location) {
+        Ok(status) => {
+            println!(
+                "{} ({}): {}",
+                location.path.display(),
+                location.source.describe(),
+                status
+            );
+            0
+        }
+        Err(errors) => {
+            for err in errors {
+                eprintln!("promptpath: {}", err);
+            }
+            1
+        }
+    }
+}
+
+// Loads the config file at location, returning a short description of what was found or every
+// problem with it
+fn describe_config(
+    home: &Path,
+    location: &ConfigLocation,
+) -> Result<String, Vec<config::ConfigError>> {
+    let config = match config::load(&location.path) {
+        Ok(config) => config,
+        Err(err) if err.is_missing() && !location.is_explicit() => {
+            return Ok(String::from("not found, using defaults"));
+        }
+        Err(err) => return Err(vec![err]),
+    };
+
+    let projects = config.projects.len();
+    let code_roots = config.code_roots.len();
+    let var = |name: &str| env::var_os(name);
+    let user_home = |name: &str| passwd::by_name(name).map(|user| user.home);
+    let (_, _, mut errors) = build_project_mappings(home, config.projects, var, user_home);
+    errors.extend(build_code_roots(home, config.code_roots, var, user_home).1);
+    if !errors.is_empty() {
+        let errors = errors
+            .into_iter()
+            .map(|source| config::ConfigError::InvalidMapping {
+                path: location.path.clone(),
+                source,
+            })
+            .collect();
+        return Err(errors);
+    }
+
+    Ok(format!(
+        "ok ({} projects, {} code roots)",
+        projects, code_roots
+    ))
+}
+
+// Prints which config file was used and how the nickname of each path was produced
+fn run_explain(ctx: &AppContext, config_flag: Option<&Path>, paths: Vec<PathBuf>, physical: bool) {
+    let sudo_user = passwd::sudo_user();
+    let (location, home) = locate_config(config_flag, ctx.home.as_deref(), sudo_user.as_ref());
+    let status = match describe_config(home, &location) {
+        Ok(status) => status,
+        Err(errors) => errors
+            .iter()
+            .map(|err| err.to_string())
+            .collect::<Vec<_>>()
+            .join("; "),
+    };
+    println!(
+        "config:   {} ({}, {})",
+        location.path.display(),
+        location.source.describe(),
+        status
+    );
+
+    let cwd = current_dir(physical).unwrap_or_default();
+    let paths = if paths.is_empty() {
+        vec![cwd.clone()]
+    } else {
+        paths
+    };
+    for path in paths {
+        let path = cli::normalize_path(&cwd, &path);
+        let nickname = resolve_nickname(ctx, &path);
+        let alias = match nickname.rule {
+            Rule::None => "-",
+            _ if nickname.alias.is_empty() => "-",
+            _ => nickname.alias.as_str(),
+        };
+
+        println!();
+        println!(
+            "path:     {}",
+            encoding::render(path.as_os_str(), &ctx.invalid_utf8)
+        );
+        println!(
+            "nickname: {}{}",
+            nickname,
+            get_branch_segment(ctx, &path, None)
+        );
+        println!("rule:     {}", nickname.rule.name());
+        println!("alias:    {}", alias);
+    }
 }
 
 fn main() {
-    let ctx = AppContext::new();
-    println!("{}", get_cwd_nickname(&ctx));
+    let args = match cli::parse(env::args_os().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("promptpath: {}", err);
+            eprint!("{}", cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let config_flag = args.config.as_deref();
+    match args.command {
+        cli::Command::Help => print!("{}", cli::USAGE),
+        cli::Command::Version => println!("promptpath {}", env!("CARGO_PKG_VERSION")),
+        cli::Command::Check => process::exit(run_check(config_flag)),
+        cli::Command::Init { shell, daemon } => {
+            let exe = env::current_exe().unwrap_or_else(|_| PathBuf::from("promptpath"));
+            print!("{}", shell::init_script(shell, &exe, daemon));
+        }
+        cli::Command::Daemon => process::exit(run_daemon(config_flag)),
+        cli::Command::Bench => {
+            if let Err(err) = bench::run() {
+                eprintln!("promptpath: {}", err);
+                process::exit(1);
+            }
+        }
+        cli::Command::Print {
+            paths,
+            explain: true,
+            physical,
+            ..
+        } => {
+            let ctx = AppContext::new(config_flag);
+            run_explain(&ctx, config_flag, paths, physical);
+        }
+        cli::Command::Print {
+            paths,
+            escape,
+            physical,
+            daemon,
+            ..
+        } => {
+            if daemon {
+                if let Some(nicknames) = query_daemon(config_flag, &paths, physical, escape) {
+                    for nickname in nicknames {
+                        println!("{}", nickname);
+                    }
+                    return;
+                }
+            }
+
+            let ctx = AppContext::new(config_flag);
+            if paths.is_empty() {
+                println!("{}", get_cwd_nickname(&ctx, physical, escape));
+            } else {
+                for nickname in get_path_nicknames(&ctx, paths, physical, escape) {
+                    println!("{}", nickname);
+                }
+            }
+        }
+        cli::Command::Batch {
+            delimiter,
+            json,
+            physical,
+        } => {
+            let mut live = LiveContext::new(config_flag);
+            let cwd = current_dir(physical).unwrap_or_default();
+            let stdin = io::stdin().lock();
+            let stdout = io::BufWriter::new(io::stdout().lock());
+            if let Err(err) = batch::run(&mut live, &cwd, stdin, stdout, delimiter, json) {
+                // A closed pipe just means the consumer has all it wanted
+                if err.kind() != io::ErrorKind::BrokenPipe {
+                    eprintln!("promptpath: {}", err);
+                    process::exit(1);
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
+    use config::InvalidUtf8Mode;
+    use std::ffi::OsStr;
+    use std::fs;
 
     #[derive(Debug)]
     struct PathTest {
@@ -192,23 +1168,31 @@ mod tests {
         description: &'static str,
     }
 
-    fn setup_test_context() -> AppContext {
+    pub(crate) fn setup_test_context() -> AppContext {
         let home = PathBuf::from("/Users/tcrypt");
-        let project_mappings = {
-            let mut m = HashMap::new();
-            m.insert(
-                PathBuf::from("/Users/tcrypt/code/github.com/tyler-smith/promptpath"),
-                (
-                    String::from("~/code/github.com/tyler-smith/promptpath"),
-                    String::from("promptpath"),
-                ),
-            );
-            m
-        };
+        let project_mappings = ProjectMappings::from([(
+            PathBuf::from("/Users/tcrypt/code/github.com/tyler-smith/promptpath"),
+            String::from("promptpath"),
+        )]);
+
+        let code_roots = vec![CodeRoot {
+            path: PathBuf::from("/Users/tcrypt/code"),
+            label: None,
+        }];
 
         AppContext {
-            home,
+            config_path: PathBuf::new(),
+            home: Some(home),
+            home_identity: None,
             project_mappings,
+            project_patterns: Vec::new(),
+            code_roots,
+            git: GitConfig::default(),
+            abbreviate: AbbreviateConfig::default(),
+            invalid_utf8: InvalidUtf8Config::default(),
+            paths: PathsConfig::default(),
+            sudo_users: Vec::new(),
+            root_marker: String::new(),
         }
     }
 
@@ -278,10 +1262,821 @@ mod tests {
         ];
 
         let ctx = setup_test_context();
+        check_paths(&ctx, test_cases);
+    }
+
+    #[test]
+    fn test_logical_paths() {
+        let root = scratch_dir("logical-paths");
+        let real = root.join("mnt/ssd/projects/promptpath");
+        fs::create_dir_all(real.join("src")).unwrap();
+        let link = root.join("proj");
+        std::os::unix::fs::symlink(root.join("mnt/ssd/projects"), &link).unwrap();
+        let logical = link.join("promptpath/src");
+
+        assert!(is_logical_path(&logical, &real.join("src")));
+        assert!(!is_logical_path(
+            &link.join("promptpath"),
+            &real.join("src")
+        ));
+        assert!(!is_logical_path(
+            &link.join("promptpath/../promptpath/src"),
+            &real.join("src")
+        ));
+        assert!(!is_logical_path(Path::new("proj/promptpath/src"), &real));
+        assert!(!is_logical_path(&root.join("absent"), &real));
+
+        // Mappings naming the real directory only match the logical path with match_physical
+        let mut ctx = setup_test_context();
+        ctx.project_mappings = ProjectMappings::from([(real.clone(), String::from("promptpath"))]);
+        assert_ne!(get_nickname(&ctx, logical.clone()), "promptpath/src");
+        ctx.paths.match_physical = true;
+        assert_eq!(get_nickname(&ctx, logical.clone()), "promptpath/src");
+
+        // Mappings naming the symlink always match the logical path
+        ctx.project_mappings
+            .insert(&link.join("promptpath"), String::from("linked"));
+        assert_eq!(get_nickname(&ctx, logical), "linked/src");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_home_routes() {
+        let root = scratch_dir("home-routes");
+        fs::create_dir_all(root.join("var/home/tcrypt/code/promptpath/src")).unwrap();
+        std::os::unix::fs::symlink(root.join("var/home"), root.join("home")).unwrap();
+        let physical = root.join("var/home/tcrypt");
+        let logical = root.join("home/tcrypt");
+        let outside = root.join("var").to_string_lossy().into_owned();
+
+        // A symlinked home is matched by its resolved path, a physical one by its inode when
+        // reached through a symlink. Mappings written with either route match paths taking it.
+        for (home, other) in [(&logical, &physical), (&physical, &logical)] {
+            let mut ctx = setup_test_context();
+            ctx.home = Some(home.clone());
+            ctx.home_identity = HomeIdentity::new(home);
+            ctx.code_roots[0].path = home.join("code");
+            ctx.code_roots.push(CodeRoot {
+                path: other.join("work"),
+                label: Some(String::from("work")),
+            });
+            ctx.project_mappings =
+                ProjectMappings::from([(other.join("notes"), String::from("notes"))]);
+            rebase_config_paths(
+                home,
+                ctx.home_identity.as_ref().unwrap(),
+                &mut ctx.project_mappings,
+                &mut ctx.code_roots,
+            );
+
+            let test_cases = [
+                (other.clone(), "~"),
+                (other.join("code/promptpath/src"), "promptpath/src"),
+                (other.join("notes/2024"), "notes/2024"),
+                (other.join("work/api"), "work/api"),
+                (other.join("data"), "~/data"),
+                (root.join("var"), outside.as_str()),
+            ];
+            for (input, expected) in test_cases {
+                assert_eq!(
+                    get_nickname(&ctx, input.clone()),
+                    expected,
+                    "Failed test: {:?}",
+                    input
+                );
+            }
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_no_home() {
+        let test_cases = vec![
+            PathTest {
+                input: "/Users/tcrypt".into(),
+                expected: "/Users/tcrypt".into(),
+                description: "Home directory isn't collapsed",
+            },
+            PathTest {
+                input: "/Users/tcrypt/data".into(),
+                expected: "/Users/tcrypt/data".into(),
+                description: "Directory in home isn't collapsed",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code/github.com/tyler-smith/promptpath/src".into(),
+                expected: "promptpath/src".into(),
+                description: "Project mappings still apply",
+            },
+        ];
+
+        let mut ctx = setup_test_context();
+        ctx.home = None;
+        check_paths(&ctx, test_cases);
+    }
+
+    #[test]
+    fn test_user_homes() {
+        let mut ctx = setup_test_context();
+        let root = passwd::by_uid(0).unwrap();
+        let input = root.home.join("projects/x");
+        let verbatim = input.to_string_lossy().into_owned();
+        assert_eq!(get_nickname(&ctx, input.clone()), verbatim);
+
+        ctx.paths.user_homes = true;
+        // Only homes next to the current one are looked up
+        assert_eq!(get_nickname(&ctx, input.clone()), verbatim);
+
+        let home = ctx
+            .home
+            .replace(root.home.with_file_name("promptpath-test-home"));
+        if root.can_log_in() {
+            let expected = format!("~{}/projects/x", root.name);
+            assert_eq!(get_nickname(&ctx, input.clone()), expected);
+            assert_eq!(
+                get_nickname(&ctx, root.home.clone()),
+                format!("~{}", root.name)
+            );
+        }
+        assert_eq!(
+            get_nickname(&ctx, PathBuf::from("/no/such/user")),
+            "/no/such/user"
+        );
+        ctx.home = home;
+
+        // Other users' projects can be mapped with ~user
+        let user_home = |name: &str| match name {
+            "alice" => Some(PathBuf::from("/home/alice")),
+            _ => None,
+        };
+        let projects = vec![
+            ProjectMapping {
+                path: String::from("~alice/code/api"),
+                alias: String::from("alice-api"),
+                kind: MappingKind::Literal,
+            },
+            ProjectMapping {
+                path: String::from("~bob/code/api"),
+                alias: String::from("bob-api"),
+                kind: MappingKind::Literal,
+            },
+        ];
+        let (mappings, _, errors) =
+            build_project_mappings(ctx.home.as_deref().unwrap(), projects, no_vars, user_home);
+        ctx.project_mappings = mappings;
+        assert_eq!(
+            get_nickname(&ctx, PathBuf::from("/home/alice/code/api/src")),
+            "alice-api/src"
+        );
+        assert_eq!(
+            errors,
+            vec![MappingError::UnknownUser {
+                path: String::from("~bob/code/api"),
+                user: String::from("bob"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sudo_users() {
+        let mut ctx = setup_test_context();
+        ctx.sudo_users = vec![
+            passwd::User {
+                name: String::from("alice"),
+                uid: 1000,
+                home: PathBuf::from("/home/alice"),
+                shell: PathBuf::from("/bin/bash"),
+            },
+            passwd::User {
+                name: String::from("admin"),
+                uid: 1001,
+                home: PathBuf::from("/home/alice/admin"),
+                shell: PathBuf::from("/usr/sbin/nologin"),
+            },
+        ];
+        ctx.root_marker = String::from(" #");
+
+        // The users involved in sudo collapse without user_homes, the deepest home first
+        let test_cases = [
+            ("/home/alice/notes", "~alice/notes #"),
+            ("/home/alice/admin/logs", "~admin/logs #"),
+            ("/Users/tcrypt/data", "~/data #"),
+            ("/home/bob", "/home/bob #"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(
+                get_display(&ctx, PathBuf::from(input), None),
+                expected,
+                "Failed test: {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_lost_cwd() {
+        let test_cases = [
+            (
+                Some("/Users/tcrypt/code/github.com/tyler-smith/promptpath/src"),
+                io::ErrorKind::NotFound,
+                "promptpath/src (deleted)",
+            ),
+            (
+                Some("/Users/tcrypt/data/../private"),
+                io::ErrorKind::PermissionDenied,
+                "~/private (no access)",
+            ),
+            (Some("/tmp"), io::ErrorKind::Other, "/tmp"),
+            (Some("data"), io::ErrorKind::NotFound, "unknown (deleted)"),
+            (None, io::ErrorKind::PermissionDenied, "unknown (no access)"),
+        ];
+
+        let ctx = setup_test_context();
+        for (pwd, kind, expected) in test_cases {
+            assert_eq!(
+                get_lost_cwd_display(&ctx, pwd.map(OsString::from), &kind.into(), None),
+                expected,
+                "Failed test: {:?} {:?}",
+                pwd,
+                kind
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_searchable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = scratch_dir("searchable");
+        let open = root.join("open");
+        let closed = root.join("closed");
+        fs::create_dir_all(&open).unwrap();
+        fs::create_dir_all(&closed).unwrap();
+        fs::set_permissions(&closed, fs::Permissions::from_mode(0o000)).unwrap();
+
+        // Root can search any directory
+        let closed_kind = if passwd::effective_uid() == 0 {
+            None
+        } else {
+            Some(io::ErrorKind::PermissionDenied)
+        };
+        let test_cases = [
+            (open.clone(), None),
+            (closed.clone(), closed_kind),
+            (root.join("missing"), Some(io::ErrorKind::NotFound)),
+        ];
+        for (dir, expected) in &test_cases {
+            assert_eq!(
+                check_searchable(dir).err().map(|err| err.kind()),
+                *expected,
+                "Failed test: {:?}",
+                dir
+            );
+        }
+
+        fs::set_permissions(&closed, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let test_cases: [(&[u8], &str, &str); 4] = [
+            (b"/Users/tcrypt/caf\xe9", r"~/caf\xe9", "~/caf?"),
+            (
+                b"/Users/tcrypt/code/github.com/tyler-smith/promptpath/\xff/src",
+                r"promptpath/\xff/src",
+                "promptpath/?/src",
+            ),
+            (b"/Users/tcrypt/code/\xe2\x82", r"\xe2\x82", "?"),
+            (b"/tmp/\xff\xfe", r"/tmp/\xff\xfe", "/tmp/??"),
+        ];
+
+        let mut ctx = setup_test_context();
+        for (input, escaped, replaced) in test_cases {
+            let path = PathBuf::from(OsStr::from_bytes(input));
+            ctx.invalid_utf8.mode = InvalidUtf8Mode::Escape;
+            assert_eq!(
+                get_nickname(&ctx, path.clone()),
+                escaped,
+                "Failed test: {:?}",
+                path
+            );
+            ctx.invalid_utf8.mode = InvalidUtf8Mode::Replace;
+            ctx.invalid_utf8.replacement = String::from("?");
+            assert_eq!(
+                get_nickname(&ctx, path.clone()),
+                replaced,
+                "Failed test: {:?}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_code_roots() {
+        let test_cases = vec![
+            PathTest {
+                input: "/Users/tcrypt/src".into(),
+                expected: "src".into(),
+                description: "Code root without a label",
+            },
+            PathTest {
+                input: "/Users/tcrypt/src/tokio".into(),
+                expected: "tokio".into(),
+                description: "Project in a code root without a label",
+            },
+            PathTest {
+                input: "/Users/tcrypt/go/src".into(),
+                expected: "go".into(),
+                description: "Code root with a label",
+            },
+            PathTest {
+                input: "/Users/tcrypt/go/src/github.com/btcsuite".into(),
+                expected: "go/github.com/btcsuite".into(),
+                description: "Project in a code root with a label",
+            },
+            PathTest {
+                input: "/Users/tcrypt/go/pkg".into(),
+                expected: "~/go/pkg".into(),
+                description: "Sibling of a code root",
+            },
+            PathTest {
+                input: "/Users/tcrypt/go/src/corp/api".into(),
+                expected: "api".into(),
+                description: "Longest code root wins",
+            },
+            PathTest {
+                input: "/Users/tcrypt/srcfiles".into(),
+                expected: "~/srcfiles".into(),
+                description: "Code root only matches whole components",
+            },
+            PathTest {
+                input: "/work/payments".into(),
+                expected: "work/payments".into(),
+                description: "Code root outside of home",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code/github.com".into(),
+                expected: "~/code/github.com".into(),
+                description: "Default code root is replaced by configured roots",
+            },
+        ];
+
+        let mut ctx = setup_test_context();
+        ctx.code_roots = vec![
+            CodeRoot {
+                path: PathBuf::from("/Users/tcrypt/src"),
+                label: None,
+            },
+            CodeRoot {
+                path: PathBuf::from("/Users/tcrypt/go/src"),
+                label: Some(String::from("go")),
+            },
+            CodeRoot {
+                path: PathBuf::from("/Users/tcrypt/go/src/corp"),
+                label: None,
+            },
+            CodeRoot {
+                path: PathBuf::from("/work"),
+                label: Some(String::from("work")),
+            },
+        ];
+        check_paths(&ctx, test_cases);
+    }
+
+    #[test]
+    fn test_abbreviate_fish() {
+        let test_cases = vec![
+            PathTest {
+                input: "/Users/tcrypt/data/archive/2024/customers/exports".into(),
+                expected: "~/d/a/2/c/exports".into(),
+                description: "Nested directory in home",
+            },
+            PathTest {
+                input: "/Users/tcrypt/.config/promptpath".into(),
+                expected: "~/.c/promptpath".into(),
+                description: "Hidden directory in home",
+            },
+            PathTest {
+                input: "/usr/share/doc".into(),
+                expected: "/u/s/doc".into(),
+                description: "System directory",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code/github.com/tyler-smith/promptpath/src/bin".into(),
+                expected: "promptpath/s/bin".into(),
+                description: "Project alias is never abbreviated",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code/github.com/go-bip39".into(),
+                expected: "g/go-bip39".into(),
+                description: "Directory in code root",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code".into(),
+                expected: "code".into(),
+                description: "Code root directory",
+            },
+        ];
+
+        let mut ctx = setup_test_context();
+        ctx.abbreviate.mode = AbbreviateMode::Fish;
+        check_paths(&ctx, test_cases);
+    }
+
+    #[test]
+    fn test_abbreviate_unique() {
+        let root = scratch_dir("abbreviate-unique");
+        let home = root.join("home");
+        let project = home.join("code/github.com/tyler-smith/promptpath");
+        fs::create_dir_all(project.join("src/bin")).unwrap();
+        fs::create_dir_all(project.join("scripts")).unwrap();
+        fs::create_dir_all(home.join("code/github.com/tokio-rs/tokio")).unwrap();
+        fs::create_dir_all(home.join("data/archive")).unwrap();
+        fs::create_dir_all(home.join("documents")).unwrap();
+
+        let mut ctx = setup_test_context();
+        ctx.home = Some(home.clone());
+        ctx.code_roots[0].path = home.join("code");
+        ctx.project_mappings =
+            ProjectMappings::from([(project.clone(), String::from("promptpath"))]);
+        ctx.abbreviate.mode = AbbreviateMode::Unique;
+
+        let test_cases = vec![
+            PathTest {
+                input: home
+                    .join("code/github.com/tokio-rs/tokio")
+                    .to_string_lossy()
+                    .into(),
+                expected: "g/to/tokio".into(),
+                description: "Directory in code root",
+            },
+            PathTest {
+                input: home.join("data/archive").to_string_lossy().into(),
+                expected: "~/da/archive".into(),
+                description: "Directory in home",
+            },
+            PathTest {
+                input: project.join("src/bin").to_string_lossy().into(),
+                expected: "promptpath/sr/bin".into(),
+                description: "Project alias is never abbreviated",
+            },
+        ];
+        check_paths(&ctx, test_cases);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_project_patterns() {
+        let test_cases = vec![
+            PathTest {
+                input: "/Users/tcrypt/code/github.com/btcsuite/btcd".into(),
+                expected: "btcsuite:btcd".into(),
+                description: "Glob pattern",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code/github.com/btcsuite/btcd/wire".into(),
+                expected: "btcsuite:btcd/wire".into(),
+                description: "Subdirectory of glob pattern",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code/github.com/tyler-smith/promptpath/src".into(),
+                expected: "promptpath/src".into(),
+                description: "Literal mapping wins a tie with a pattern",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code/github.com/tyler-smith/promptpath/contrib/vim".into(),
+                expected: "contrib:vim".into(),
+                description: "Longer pattern wins over a literal mapping",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code/gitlab.com/corp/api/v2".into(),
+                expected: "corp-api/v2".into(),
+                description: "Regex pattern",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code/github.com/btcsuite".into(),
+                expected: "github.com/btcsuite".into(),
+                description: "Too shallow for the pattern",
+            },
+        ];
+
+        let projects = vec![
+            ProjectMapping {
+                path: String::from("~/code/github.com/{org}/{repo}"),
+                alias: String::from("{org}:{repo}"),
+                kind: MappingKind::Glob,
+            },
+            ProjectMapping {
+                path: String::from("~/code/github.com/*/promptpath/contrib/*"),
+                alias: String::from("contrib:$2"),
+                kind: MappingKind::Glob,
+            },
+            ProjectMapping {
+                path: String::from(r"~/code/gitlab\.com/([^/]+)/([^/]+)"),
+                alias: String::from("$1-$2"),
+                kind: MappingKind::Regex,
+            },
+            ProjectMapping {
+                path: String::from("~/code/{org"),
+                alias: String::from("broken"),
+                kind: MappingKind::Glob,
+            },
+        ];
+
+        let mut ctx = setup_test_context();
+        let (_, patterns, errors) =
+            build_project_mappings(ctx.home.as_deref().unwrap(), projects, no_vars, no_users);
+        assert_eq!(
+            errors,
+            vec![MappingError::Pattern(
+                pattern::PatternError::UnterminatedCapture(String::from("~/code/{org"))
+            )]
+        );
+        ctx.project_patterns = patterns;
+        check_paths(&ctx, test_cases);
+    }
+
+    #[test]
+    fn test_project_mapping_spellings() {
+        let spellings = [
+            "~/code/github.com/tyler-smith/promptpath",
+            "~/code/github.com/tyler-smith/promptpath/",
+            "/Users/tcrypt/code/github.com/tyler-smith/promptpath",
+            "/Users/tcrypt//code/github.com/tyler-smith/promptpath//",
+            "~/code/./github.com/tokio-rs/../tyler-smith/promptpath",
+            "code/github.com/tyler-smith/promptpath",
+        ];
+
+        for spelling in spellings {
+            let test_cases = vec![
+                PathTest {
+                    input: "/Users/tcrypt/code/github.com/tyler-smith/promptpath".into(),
+                    expected: "promptpath".into(),
+                    description: spelling,
+                },
+                PathTest {
+                    input: "/Users/tcrypt/code/github.com/tyler-smith/promptpath/src".into(),
+                    expected: "promptpath/src".into(),
+                    description: spelling,
+                },
+            ];
+
+            let mut ctx = setup_test_context();
+            let projects = vec![ProjectMapping {
+                path: String::from(spelling),
+                alias: String::from("promptpath"),
+                kind: MappingKind::Literal,
+            }];
+            (ctx.project_mappings, _, _) =
+                build_project_mappings(ctx.home.as_deref().unwrap(), projects, no_vars, no_users);
+            check_paths(&ctx, test_cases);
+        }
+    }
+
+    #[test]
+    fn test_env_var_mappings() {
+        let var = |name: &str| match name {
+            "WORKSPACE" => Some(OsString::from("/work")),
+            "GOPATH" => Some(OsString::from("/Users/tcrypt/go")),
+            _ => None,
+        };
+        let test_cases = vec![
+            PathTest {
+                input: "/work/api/handlers".into(),
+                expected: "api/handlers".into(),
+                description: "Mapping using $VAR",
+            },
+            PathTest {
+                input: "/work/web".into(),
+                expected: "web".into(),
+                description: "Glob mapping using $VAR",
+            },
+            PathTest {
+                input: "/Users/tcrypt/corp/billing".into(),
+                expected: "billing".into(),
+                description: "Mapping using a default for an unset variable",
+            },
+            PathTest {
+                input: "/Users/tcrypt/go/src/github.com/btcsuite".into(),
+                expected: "go/github.com/btcsuite".into(),
+                description: "Code root using ${VAR}",
+            },
+            PathTest {
+                input: "/Users/tcrypt/code/github.com".into(),
+                expected: "~/code/github.com".into(),
+                description: "Code root using an unset variable is skipped",
+            },
+        ];
+
+        let projects = vec![
+            ProjectMapping {
+                path: String::from("$WORKSPACE/api"),
+                alias: String::from("api"),
+                kind: MappingKind::Literal,
+            },
+            ProjectMapping {
+                path: String::from("${WORKSPACE}/{name}"),
+                alias: String::from("{name}"),
+                kind: MappingKind::Glob,
+            },
+            ProjectMapping {
+                path: String::from("${CORP:-~/corp}/billing"),
+                alias: String::from("billing"),
+                kind: MappingKind::Literal,
+            },
+            ProjectMapping {
+                path: String::from("$MISSING/x"),
+                alias: String::from("x"),
+                kind: MappingKind::Literal,
+            },
+        ];
+        let code_roots = vec![
+            CodeRootMapping {
+                path: String::from("${GOPATH}/src"),
+                label: Some(String::from("go")),
+            },
+            CodeRootMapping {
+                path: String::from("$CODE"),
+                label: None,
+            },
+        ];
+
+        let mut ctx = setup_test_context();
+        let (mappings, patterns, errors) =
+            build_project_mappings(ctx.home.as_deref().unwrap(), projects, var, no_users);
+        assert_eq!(
+            errors,
+            vec![MappingError::UnsetVariable {
+                path: String::from("$MISSING/x"),
+                variable: String::from("MISSING"),
+            }]
+        );
+        let (code_roots, errors) =
+            build_code_roots(ctx.home.as_deref().unwrap(), code_roots, var, no_users);
+        assert_eq!(
+            errors,
+            vec![MappingError::UnsetVariable {
+                path: String::from("$CODE"),
+                variable: String::from("CODE"),
+            }]
+        );
+
+        ctx.project_mappings = mappings;
+        ctx.project_patterns = patterns;
+        ctx.code_roots = code_roots;
+        check_paths(&ctx, test_cases);
+    }
+
+    #[test]
+    fn test_git_alias() {
+        let root = scratch_dir("git-alias");
+        let home = root.join("home");
+        let org = home.join("code/github.com/org");
+        fs::create_dir_all(home.join(".git")).unwrap();
+        fs::create_dir_all(home.join("notes")).unwrap();
+        fs::create_dir_all(org.join("repo/.git")).unwrap();
+        fs::create_dir_all(org.join("repo/src/deep")).unwrap();
+        fs::create_dir_all(org.join("mapped/.git")).unwrap();
+        fs::create_dir_all(org.join("mapped/src")).unwrap();
+        fs::create_dir_all(org.join("worktree")).unwrap();
+        fs::write(
+            org.join("worktree/.git"),
+            "gitdir: ../repo/.git/worktrees/wt\n",
+        )
+        .unwrap();
+
+        let mut ctx = setup_test_context();
+        ctx.home = Some(home.clone());
+        ctx.code_roots[0].path = home.join("code");
+        ctx.project_mappings
+            .insert(&org.join("mapped"), String::from("mapped"));
+        ctx.git.alias = true;
+
+        let test_cases = vec![
+            PathTest {
+                input: org.join("repo").to_string_lossy().into(),
+                expected: "repo".into(),
+                description: "Repository root",
+            },
+            PathTest {
+                input: org.join("repo/src/deep").to_string_lossy().into(),
+                expected: "repo/src/deep".into(),
+                description: "Directory inside a repository",
+            },
+            PathTest {
+                input: org.join("worktree").to_string_lossy().into(),
+                expected: "worktree".into(),
+                description: "Repository with a .git file",
+            },
+            PathTest {
+                input: org.join("mapped/src").to_string_lossy().into(),
+                expected: "mapped/src".into(),
+                description: "Project mappings take precedence",
+            },
+            PathTest {
+                input: org.to_string_lossy().into(),
+                expected: "github.com/org".into(),
+                description: "Directory outside of a repository",
+            },
+            PathTest {
+                input: home.join("notes").to_string_lossy().into(),
+                expected: "~/notes".into(),
+                description: "Repository in home is ignored",
+            },
+        ];
+        check_paths(&ctx, test_cases);
+
+        ctx.git.alias_prefix = String::from("@");
+        let test_cases = vec![PathTest {
+            input: org.join("repo/src").to_string_lossy().into(),
+            expected: "@repo/src".into(),
+            description: "Repository alias with a prefix",
+        }];
+        check_paths(&ctx, test_cases);
+
+        ctx.git.alias = false;
+        let test_cases = vec![PathTest {
+            input: org.join("repo/src").to_string_lossy().into(),
+            expected: "github.com/org/repo/src".into(),
+            description: "Repository aliases disabled",
+        }];
+        check_paths(&ctx, test_cases);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_branch_segment() {
+        let root = scratch_dir("branch-segment");
+        let repo = root.join("repo");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::write(repo.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let mut ctx = setup_test_context();
+        let input = repo.join("src");
+        let nickname = get_nickname(&ctx, input.clone());
+        assert_eq!(get_display(&ctx, input.clone(), None), nickname);
+
+        ctx.git.branch = true;
+        assert_eq!(
+            get_display(&ctx, input.clone(), None),
+            format!("{} (main)", nickname)
+        );
+
+        ctx.git.branch_format = String::from(" on {branch}");
+        assert_eq!(
+            get_display(&ctx, input.clone(), None),
+            format!("{} on main", nickname)
+        );
+
+        let detached = "8f1a0b6c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a";
+        fs::write(repo.join(".git/HEAD"), detached).unwrap();
+        assert_eq!(
+            get_display(&ctx, input.clone(), None),
+            format!("{} ((8f1a0b6))", nickname)
+        );
+
+        assert_eq!(
+            get_display(&ctx, root.clone(), None),
+            get_nickname(&ctx, root.clone())
+        );
+
+        // Branch names are escaped but the format isn't
+        fs::write(repo.join(".git/HEAD"), "ref: refs/heads/100%-$(done)\n").unwrap();
+        ctx.git.branch_format = String::from(" %F{green}{branch}%f");
+        assert_eq!(
+            get_display(&ctx, input.clone(), Some(Escape::Zsh)),
+            format!("{} %F{{green}}100%%-\\$(done)%f", nickname)
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    fn no_vars(_: &str) -> Option<OsString> {
+        None
+    }
+
+    fn no_users(_: &str) -> Option<PathBuf> {
+        None
+    }
+
+    // Creates an empty scratch directory unique to the calling test
+    pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("promptpath-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn check_paths(ctx: &AppContext, test_cases: Vec<PathTest>) {
         for test in test_cases {
             // Test path
             let path = PathBuf::from(&test.input);
-            let result = get_nickname(&ctx, path.clone());
+            let result = get_nickname(ctx, path.clone());
             assert_eq!(
                 result, test.expected,
                 "Failed test '{}': expected '{}', got '{}'",
@@ -289,9 +2084,9 @@ mod tests {
             );
 
             // Test path with a trailing slash
-            if path != PathBuf::from("/") {
+            if path != Path::new("/") {
                 let path = path.join("");
-                let result = get_nickname(&ctx, path.clone());
+                let result = get_nickname(ctx, path.clone());
                 assert_eq!(
                     result, test.expected,
                     "Failed test '{}': expected '{}', got '{}'",
@@ -0,0 +1,209 @@
+use std::env;
+use std::ffi::{CStr, CString, OsStr};
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr;
+
+// Largest buffer offered to getpwuid_r and getpwnam_r before giving up on an entry
+const MAX_BUFFER_LEN: usize = 1 << 20;
+
+// A user from the passwd database
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub name: String,
+    pub uid: u32,
+    pub home: PathBuf,
+    pub shell: PathBuf,
+}
+
+impl User {
+    // Returns false for system accounts whose shell refuses logins, like nologin or false. An
+    // empty shell means /bin/sh.
+    pub fn can_log_in(&self) -> bool {
+        let shell = self.shell.file_name().unwrap_or_default();
+        shell != "nologin" && shell != "false"
+    }
+}
+
+// Returns the home directory from $HOME, or from the passwd entry of the current user when HOME
+// is unset, empty or relative, as it can be in systemd units, env -i shells and containers.
+// Returns None if neither names a directory.
+pub fn home_dir() -> Option<PathBuf> {
+    choose_home(env::var_os("HOME").map(PathBuf::from), || {
+        by_uid(current_uid()).map(|user| user.home)
+    })
+}
+
+// Picks the home directory from home, falling back to user_home. A home of / counts as none, since
+// it's what many system users like systemd-network have and would turn every path into ~/...
+fn choose_home(
+    home: Option<PathBuf>,
+    user_home: impl FnOnce() -> Option<PathBuf>,
+) -> Option<PathBuf> {
+    let usable = |home: &PathBuf| home.is_absolute() && home.parent().is_some();
+    match home {
+        Some(home) if usable(&home) => Some(home),
+        Some(home) if home.is_absolute() => None,
+        _ => user_home().filter(usable),
+    }
+}
+
+// Returns the real user id of the process
+pub fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and can't fail
+    unsafe { libc::getuid() }
+}
+
+// Returns the effective user id of the process, which is 0 under sudo
+pub fn effective_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and can't fail
+    unsafe { libc::geteuid() }
+}
+
+// Returns the user who ran sudo when running as root through it, with their home from SUDO_HOME
+// when that's set. Returns None otherwise, including when root itself ran sudo.
+pub fn sudo_user() -> Option<User> {
+    if effective_uid() != 0 {
+        return None;
+    }
+    let name = env::var("SUDO_USER").ok()?;
+    if name.is_empty() || name == "root" {
+        return None;
+    }
+
+    let mut user = by_name(&name)?;
+    let sudo_home = env::var_os("SUDO_HOME").map(PathBuf::from);
+    if let Some(home) = sudo_home.filter(|home| home.is_absolute()) {
+        user.home = home;
+    }
+    Some(user)
+}
+
+// Looks up a user in the passwd database by user id, which also covers users from NSS sources
+// like LDAP that aren't in /etc/passwd
+pub fn by_uid(uid: u32) -> Option<User> {
+    // SAFETY: the arguments are passed through from lookup, which keeps them valid for the call
+    lookup(|entry, buffer, len, result| unsafe {
+        libc::getpwuid_r(uid, entry, buffer, len, result)
+    })
+}
+
+// Looks up a user in the passwd database by name
+pub fn by_name(name: &str) -> Option<User> {
+    let name = CString::new(name).ok()?;
+    // SAFETY: the arguments are passed through from lookup, which keeps them valid for the call,
+    // and name is NUL terminated
+    lookup(|entry, buffer, len, result| unsafe {
+        libc::getpwnam_r(name.as_ptr(), entry, buffer, len, result)
+    })
+}
+
+// Calls getpwuid_r or getpwnam_r through call, growing the buffer the entry's strings are stored
+// in until they fit
+fn lookup(
+    call: impl Fn(*mut libc::passwd, *mut libc::c_char, usize, *mut *mut libc::passwd) -> libc::c_int,
+) -> Option<User> {
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut entry = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+        let status = call(
+            entry.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        );
+        if status == libc::ERANGE && buffer.len() < MAX_BUFFER_LEN {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        if status != 0 || result.is_null() {
+            return None;
+        }
+
+        // SAFETY: the lookup succeeded, so the entry is initialized and its strings point into
+        // buffer, which outlives this block
+        let (entry, name, home, shell) = unsafe {
+            let entry = entry.assume_init();
+            (
+                entry,
+                CStr::from_ptr(entry.pw_name),
+                CStr::from_ptr(entry.pw_dir),
+                CStr::from_ptr(entry.pw_shell),
+            )
+        };
+        return Some(User {
+            name: name.to_str().ok()?.to_string(),
+            uid: entry.pw_uid,
+            home: PathBuf::from(OsStr::from_bytes(home.to_bytes())),
+            shell: PathBuf::from(OsStr::from_bytes(shell.to_bytes())),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let root = by_uid(0).unwrap();
+        assert_eq!(root.uid, 0);
+        assert!(root.home.is_absolute());
+
+        // Nobody is ever given the largest uid, which is reserved as the error value
+        assert_eq!(by_uid(u32::MAX), None);
+
+        assert_eq!(by_name(&root.name), Some(root));
+        assert_eq!(by_name("no such user"), None);
+        assert_eq!(by_name("nul\0byte"), None);
+    }
+
+    #[test]
+    fn test_choose_home() {
+        let test_cases = [
+            (Some("/home/alice"), Some("/home/bob"), Some("/home/alice")),
+            (Some(""), Some("/home/bob"), Some("/home/bob")),
+            (Some("home/alice"), Some("/home/bob"), Some("/home/bob")),
+            (None, Some("/home/bob"), Some("/home/bob")),
+            (None, Some("bob"), None),
+            (None, None, None),
+            // A home of / is no home at all
+            (Some("/"), Some("/home/bob"), None),
+            (None, Some("/"), None),
+            (Some("//"), None, None),
+        ];
+
+        for (home, user_home, expected) in test_cases {
+            assert_eq!(
+                choose_home(home.map(PathBuf::from), || user_home.map(PathBuf::from)),
+                expected.map(PathBuf::from),
+                "Failed test: {:?}",
+                (home, user_home)
+            );
+        }
+    }
+
+    #[test]
+    fn test_can_log_in() {
+        let test_cases = [
+            ("/bin/bash", true),
+            ("/usr/bin/fish", true),
+            ("/usr/sbin/nologin", false),
+            ("/sbin/nologin", false),
+            ("/bin/false", false),
+            ("", true),
+        ];
+
+        for (shell, expected) in test_cases {
+            let user = User {
+                name: String::from("alice"),
+                uid: 1000,
+                home: PathBuf::from("/home/alice"),
+                shell: PathBuf::from(shell),
+            };
+            assert_eq!(user.can_log_in(), expected, "Failed test: {}", shell);
+        }
+    }
+}
@@ -0,0 +1,505 @@
+use regex_lite::Regex;
+use std::path::{Component, Path};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum PatternError {
+    #[error("pattern '{0}' must be an absolute path or start with ~/")]
+    NotAbsolute(String),
+    #[error("invalid regex '{0}': {1}")]
+    InvalidRegex(String, String),
+    #[error("unterminated capture name in '{0}'")]
+    UnterminatedCapture(String),
+    #[error("alias '{0}' refers to capture '{1}', which the pattern doesn't have")]
+    UnknownCapture(String, String),
+}
+
+// A project mapping path that matches many directories, such as ~/code/github.com/{org}/{repo}
+// or a regex, along with the alias template its captures are substituted into
+#[derive(Debug)]
+pub struct ProjectPattern {
+    matcher: Matcher,
+    alias: String,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Glob(Vec<GlobComponent>),
+    Regex(Regex),
+}
+
+#[derive(Debug, PartialEq)]
+enum GlobComponent {
+    // ** matches any number of components, including none
+    AnyDepth,
+    // Anything else matches exactly one component
+    Tokens(Vec<GlobToken>),
+}
+
+#[derive(Debug, PartialEq)]
+enum GlobToken {
+    Literal(char),
+    // ? matches exactly one character
+    AnyChar,
+    // * matches any run of characters, {name} does the same and names the capture
+    AnyRun(Option<String>),
+}
+
+// A successful match of a pattern against a path
+#[derive(Debug, PartialEq)]
+pub struct PatternMatch {
+    // Number of path components, not counting the root, covered by the match
+    pub depth: usize,
+    // The alias with the captures substituted in
+    pub alias: String,
+}
+
+// A captured value along with its name if it had one. Captures are numbered from 1 in the order
+// they appear in the pattern.
+#[derive(Debug, Clone)]
+struct Capture {
+    name: Option<String>,
+    value: String,
+}
+
+impl ProjectPattern {
+    // Compiles a glob pattern. Each component may contain * and ? wildcards and {name} captures,
+    // which like * match any run of characters within a single component. A component of just **
+    // matches any number of components. home is used to expand a leading ~/.
+    pub fn glob(home: &Path, pattern: &str, alias: &str) -> Result<Self, PatternError> {
+        let expanded = expand_pattern_home(home, pattern, false)
+            .ok_or_else(|| PatternError::NotAbsolute(pattern.to_string()))?;
+
+        let mut components = Vec::new();
+        let mut names = Vec::new();
+        for component in expanded.split('/').filter(|c| !c.is_empty()) {
+            if component == "**" {
+                components.push(GlobComponent::AnyDepth);
+                names.push(None);
+                continue;
+            }
+            let tokens = parse_glob_component(component)
+                .ok_or_else(|| PatternError::UnterminatedCapture(pattern.to_string()))?;
+            for token in &tokens {
+                if let GlobToken::AnyRun(name) = token {
+                    names.push(name.clone());
+                }
+            }
+            components.push(GlobComponent::Tokens(tokens));
+        }
+
+        validate_alias(alias, &names)?;
+        Ok(Self {
+            matcher: Matcher::Glob(components),
+            alias: alias.to_string(),
+        })
+    }
+
+    // Compiles a regex pattern. The regex is anchored at the start of the path and must end on a
+    // component boundary, with whatever follows becoming the subpath shown after the alias. A
+    // leading ~/ is expanded to home.
+    pub fn regex(home: &Path, pattern: &str, alias: &str) -> Result<Self, PatternError> {
+        let expanded = expand_pattern_home(home, pattern, true)
+            .ok_or_else(|| PatternError::NotAbsolute(pattern.to_string()))?;
+        let regex = Regex::new(&format!("^(?:{})(?:/|$)", expanded))
+            .map_err(|err| PatternError::InvalidRegex(pattern.to_string(), err.to_string()))?;
+
+        let names: Vec<Option<String>> = regex
+            .capture_names()
+            .skip(1)
+            .map(|name| name.map(str::to_string))
+            .collect();
+        validate_alias(alias, &names)?;
+        Ok(Self {
+            matcher: Matcher::Regex(regex),
+            alias: alias.to_string(),
+        })
+    }
+
+    // Matches the pattern against the start of path, which must be absolute
+    pub fn matches(&self, path: &Path) -> Option<PatternMatch> {
+        let components: Vec<_> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+
+        let (depth, captures) = match &self.matcher {
+            Matcher::Glob(glob) => {
+                let components: Vec<&str> = components.iter().map(|c| c.as_ref()).collect();
+                let mut captures = Vec::new();
+                let depth = match_glob(glob, &components, &mut captures)?;
+                (depth, captures)
+            }
+            Matcher::Regex(regex) => {
+                let path_str = format!("/{}", components.join("/"));
+                let found = regex.captures(&path_str)?;
+                let matched = found.get(0)?.as_str().trim_end_matches('/');
+                let depth = matched.split('/').filter(|c| !c.is_empty()).count();
+                let captures = regex
+                    .capture_names()
+                    .zip(found.iter())
+                    .skip(1)
+                    .map(|(name, value)| Capture {
+                        name: name.map(str::to_string),
+                        value: value.map_or("", |v| v.as_str()).to_string(),
+                    })
+                    .collect();
+                (depth, captures)
+            }
+        };
+
+        Some(PatternMatch {
+            depth,
+            alias: expand_alias(&self.alias, &captures),
+        })
+    }
+}
+
+// Expands a leading ~/ (or a bare ~) in a pattern to home, escaping it for use in a regex if
+// needed. Returns None if the result isn't absolute.
+fn expand_pattern_home(home: &Path, pattern: &str, escape: bool) -> Option<String> {
+    let rest = match pattern.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ if pattern.starts_with('/') => return Some(pattern.to_string()),
+        _ => return None,
+    };
+
+    let home = home.to_string_lossy();
+    let home = home.trim_end_matches('/');
+    let home = if escape {
+        regex_lite::escape(home)
+    } else {
+        home.to_string()
+    };
+    Some(format!("{}{}", home, rest))
+}
+
+// Parses a glob component into tokens, or None if a {name} capture isn't closed
+fn parse_glob_component(component: &str) -> Option<Vec<GlobToken>> {
+    let mut tokens = Vec::new();
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => tokens.push(GlobToken::AnyRun(None)),
+            '?' => tokens.push(GlobToken::AnyChar),
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next()? {
+                        '}' => break,
+                        c => name.push(c),
+                    }
+                }
+                tokens.push(GlobToken::AnyRun(Some(name)));
+            }
+            c => tokens.push(GlobToken::Literal(c)),
+        }
+    }
+    Some(tokens)
+}
+
+// Matches glob against the start of components, returning the number of components matched.
+// Captures are pushed in pattern order.
+fn match_glob(
+    glob: &[GlobComponent],
+    components: &[&str],
+    captures: &mut Vec<Capture>,
+) -> Option<usize> {
+    let Some((first, rest)) = glob.split_first() else {
+        return Some(0);
+    };
+
+    match first {
+        GlobComponent::AnyDepth => {
+            // Prefer matching as many components as possible, like a greedy regex
+            for taken in (0..=components.len()).rev() {
+                let mark = captures.len();
+                captures.push(Capture {
+                    name: None,
+                    value: components[..taken].join("/"),
+                });
+                if let Some(depth) = match_glob(rest, &components[taken..], captures) {
+                    return Some(taken + depth);
+                }
+                captures.truncate(mark);
+            }
+            None
+        }
+        GlobComponent::Tokens(tokens) => {
+            let (component, remaining) = components.split_first()?;
+            let chars: Vec<char> = component.chars().collect();
+            let mark = captures.len();
+            if !match_tokens(tokens, &chars, captures) {
+                return None;
+            }
+            match match_glob(rest, remaining, captures) {
+                Some(depth) => Some(depth + 1),
+                None => {
+                    captures.truncate(mark);
+                    None
+                }
+            }
+        }
+    }
+}
+
+// Matches tokens against the whole of chars, pushing the value of each run in order
+fn match_tokens(tokens: &[GlobToken], chars: &[char], captures: &mut Vec<Capture>) -> bool {
+    let Some((first, rest)) = tokens.split_first() else {
+        return chars.is_empty();
+    };
+
+    match first {
+        GlobToken::Literal(c) => {
+            chars.first() == Some(c) && match_tokens(rest, &chars[1..], captures)
+        }
+        GlobToken::AnyChar => !chars.is_empty() && match_tokens(rest, &chars[1..], captures),
+        GlobToken::AnyRun(name) => {
+            // Runs must match at least one character so {org}/{repo} can't match empty names
+            for end in (1..=chars.len()).rev() {
+                let mark = captures.len();
+                captures.push(Capture {
+                    name: name.clone(),
+                    value: chars[..end].iter().collect(),
+                });
+                if match_tokens(rest, &chars[end..], captures) {
+                    return true;
+                }
+                captures.truncate(mark);
+            }
+            false
+        }
+    }
+}
+
+// Checks that every capture the alias refers to exists. names holds the name, if any, of each
+// capture in order.
+fn validate_alias(alias: &str, names: &[Option<String>]) -> Result<(), PatternError> {
+    for reference in alias_references(alias) {
+        let exists = match reference.parse::<usize>() {
+            Ok(index) => index <= names.len(),
+            Err(_) => names.iter().any(|name| name.as_deref() == Some(reference)),
+        };
+        if !exists {
+            return Err(PatternError::UnknownCapture(
+                alias.to_string(),
+                reference.to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Returns the capture names and numbers referred to by {name}, {N} or $N in an alias
+fn alias_references(alias: &str) -> Vec<&str> {
+    let mut references = Vec::new();
+    let mut rest = alias;
+    while let Some(start) = rest.find(['{', '$']) {
+        let (reference, len) = parse_reference(&rest[start..]);
+        if let Some(reference) = reference {
+            references.push(reference);
+        }
+        rest = &rest[start + len..];
+    }
+    references
+}
+
+// Parses a reference at the start of s, which begins with { or $. Returns the reference, if s
+// starts with a valid one, and how many bytes were consumed.
+fn parse_reference(s: &str) -> (Option<&str>, usize) {
+    if let Some(inner) = s.strip_prefix('{') {
+        if let Some(end) = inner.find('}') {
+            return (Some(&inner[..end]), end + 2);
+        }
+    } else if let Some(digits) = s.strip_prefix('$') {
+        let len = digits.bytes().take_while(u8::is_ascii_digit).count();
+        if len > 0 {
+            return (Some(&digits[..len]), len + 1);
+        }
+    }
+    (None, 1)
+}
+
+// Substitutes captures into an alias. {0} and $0 refer to the whole match, which isn't useful in
+// an alias, so they expand to nothing.
+fn expand_alias(alias: &str, captures: &[Capture]) -> String {
+    let mut result = String::with_capacity(alias.len());
+    let mut rest = alias;
+    while let Some(start) = rest.find(['{', '$']) {
+        result.push_str(&rest[..start]);
+        let (reference, len) = parse_reference(&rest[start..]);
+        match reference {
+            Some(reference) => {
+                let value = match reference.parse::<usize>() {
+                    Ok(index) => index.checked_sub(1).and_then(|i| captures.get(i)),
+                    Err(_) => captures
+                        .iter()
+                        .find(|capture| capture.name.as_deref() == Some(reference)),
+                };
+                result.push_str(value.map_or("", |capture| capture.value.as_str()));
+            }
+            None => result.push_str(&rest[start..start + len]),
+        }
+        rest = &rest[start + len..];
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME: &str = "/Users/tcrypt";
+
+    #[test]
+    fn test_glob() {
+        let home = Path::new(HOME);
+        let test_cases = [
+            (
+                "~/code/github.com/{org}/{repo}",
+                "{org}:{repo}",
+                "/Users/tcrypt/code/github.com/tyler-smith/promptpath/src",
+                Some((6, "tyler-smith:promptpath")),
+            ),
+            (
+                "~/code/github.com/*/*",
+                "$2",
+                "/Users/tcrypt/code/github.com/tyler-smith/promptpath",
+                Some((6, "promptpath")),
+            ),
+            (
+                "~/code/github.com/{org}/{repo}",
+                "{repo}",
+                "/Users/tcrypt/code/github.com/tyler-smith",
+                None,
+            ),
+            (
+                "~/code/**/go-{name}",
+                "go:{name}",
+                "/Users/tcrypt/code/github.com/tyler-smith/go-bip39/cmd",
+                Some((6, "go:bip39")),
+            ),
+            ("/srv/app-?", "app", "/srv/app-1/logs", Some((2, "app"))),
+            ("/srv/app-?", "app", "/srv/app-10", None),
+            (
+                "~/work/{client}-*",
+                "{client}/$2",
+                "/Users/tcrypt/work/acme-web-api",
+                Some((4, "acme-web/api")),
+            ),
+        ];
+
+        for (pattern, alias, path, expected) in test_cases {
+            let compiled = ProjectPattern::glob(home, pattern, alias).unwrap();
+            let result = compiled.matches(Path::new(path));
+            let expected = expected.map(|(depth, alias)| PatternMatch {
+                depth,
+                alias: alias.to_string(),
+            });
+            assert_eq!(
+                result, expected,
+                "Failed test: {} against {}",
+                pattern, path
+            );
+        }
+    }
+
+    #[test]
+    fn test_regex() {
+        let home = Path::new(HOME);
+        let test_cases = [
+            (
+                r"~/code/github\.com/(?P<org>[^/]+)/([^/]+)",
+                "{org}:$2",
+                "/Users/tcrypt/code/github.com/tyler-smith/promptpath/src",
+                Some((6, "tyler-smith:promptpath")),
+            ),
+            (
+                r"~/code/gitlab\.com/([^/]+)",
+                "$1",
+                "/Users/tcrypt/code/github.com/tyler-smith",
+                None,
+            ),
+            (r"/srv/app", "app", "/srv/application", None),
+            (
+                r"/srv/(app|web)\d+",
+                "srv:$1",
+                "/srv/web12/logs",
+                Some((2, "srv:web")),
+            ),
+        ];
+
+        for (pattern, alias, path, expected) in test_cases {
+            let compiled = ProjectPattern::regex(home, pattern, alias).unwrap();
+            let result = compiled.matches(Path::new(path));
+            let expected = expected.map(|(depth, alias)| PatternMatch {
+                depth,
+                alias: alias.to_string(),
+            });
+            assert_eq!(
+                result, expected,
+                "Failed test: {} against {}",
+                pattern, path
+            );
+        }
+    }
+
+    #[test]
+    fn test_pattern_errors() {
+        let home = Path::new(HOME);
+        assert_eq!(
+            ProjectPattern::glob(home, "code/*", "$1").unwrap_err(),
+            PatternError::NotAbsolute("code/*".into())
+        );
+        assert_eq!(
+            ProjectPattern::glob(home, "~/code/{org", "x").unwrap_err(),
+            PatternError::UnterminatedCapture("~/code/{org".into())
+        );
+        assert_eq!(
+            ProjectPattern::glob(home, "~/code/{org}", "{repo}").unwrap_err(),
+            PatternError::UnknownCapture("{repo}".into(), "repo".into())
+        );
+        assert_eq!(
+            ProjectPattern::regex(home, "~/code/([^/]+)", "$2").unwrap_err(),
+            PatternError::UnknownCapture("$2".into(), "2".into())
+        );
+        assert!(matches!(
+            ProjectPattern::regex(home, "~/code/(", "x").unwrap_err(),
+            PatternError::InvalidRegex(..)
+        ));
+    }
+
+    #[test]
+    fn test_expand_alias() {
+        let captures = vec![
+            Capture {
+                name: Some("org".into()),
+                value: "tyler-smith".into(),
+            },
+            Capture {
+                name: None,
+                value: "promptpath".into(),
+            },
+        ];
+        let test_cases = [
+            ("{org}/{2}", "tyler-smith/promptpath"),
+            ("$1:$2", "tyler-smith:promptpath"),
+            ("cost $ {", "cost $ {"),
+            ("$0", ""),
+            ("plain", "plain"),
+        ];
+
+        for (alias, expected) in test_cases {
+            assert_eq!(
+                expand_alias(alias, &captures),
+                expected,
+                "Failed test: {}",
+                alias
+            );
+        }
+    }
+}
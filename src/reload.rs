@@ -0,0 +1,328 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{self, Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::ConfigError;
+use crate::{locate_config, passwd, AppContext};
+
+// How often the config file's metadata is checked when it can't be watched
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// A context for a long-lived process like the daemon or a batch consumer, which is replaced when
+// its config file changes
+pub struct LiveContext {
+    ctx: AppContext,
+    config_flag: Option<PathBuf>,
+    watcher: Watcher,
+}
+
+impl LiveContext {
+    pub fn new(config_flag: Option<&Path>) -> Self {
+        let config_flag = pin_config(config_flag);
+        let ctx = AppContext::new(config_flag.as_deref());
+        let watcher = Watcher::new(&ctx.config_path);
+        Self {
+            ctx,
+            config_flag,
+            watcher,
+        }
+    }
+
+    // Returns the context, first reloading it if the config file has changed
+    pub fn get(&mut self) -> &AppContext {
+        if self.watcher.changed() {
+            self.reload();
+        }
+        &self.ctx
+    }
+
+    // Builds a context from the config file and swaps it in whole, so a query is always answered
+    // by one config or the other. A file that can't be read or parsed, as can happen halfway
+    // through an editor saving it, leaves the previous context in place until it's fixed.
+    fn reload(&mut self) {
+        let (ctx, err) = AppContext::load(self.config_flag.as_deref());
+        match err {
+            Some(err @ (ConfigError::FileRead { .. } | ConfigError::ParseError { .. })) => {
+                eprintln!("promptpath: kept the previous config: {}", err);
+                return;
+            }
+            Some(err) => eprintln!("promptpath: reloaded the config with problems: {}", err),
+            None => eprintln!("promptpath: reloaded {}", ctx.config_path.display()),
+        }
+
+        if ctx.config_path != self.ctx.config_path {
+            self.watcher = Watcher::new(&ctx.config_path);
+        }
+        self.ctx = ctx;
+    }
+}
+
+// Returns the flag to reload the config with. The daemon leaves the directory it was started in, so
+// a relative path from --config or PROMPTPATH_CONFIG is made absolute first or it wouldn't find the
+// file again. Both name the file outright, so one from the environment is passed on as the flag.
+// The other places the config is found are always absolute.
+fn pin_config(config_flag: Option<&Path>) -> Option<PathBuf> {
+    let home = passwd::home_dir();
+    let sudo_user = passwd::sudo_user();
+    let (location, _) = locate_config(config_flag, home.as_deref(), sudo_user.as_ref());
+    if !location.is_explicit() {
+        return None;
+    }
+    Some(path::absolute(&location.path).unwrap_or(location.path))
+}
+
+// Notices changes to a file, with inotify on Linux and by polling its metadata elsewhere, or when
+// the file's directory can't be watched
+struct Watcher {
+    path: PathBuf,
+    #[cfg(target_os = "linux")]
+    inotify: Option<inotify::Inotify>,
+    stamp: Option<Stamp>,
+    last_poll: Instant,
+}
+
+impl Watcher {
+    fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            #[cfg(target_os = "linux")]
+            inotify: inotify::Inotify::new(path),
+            stamp: Stamp::of(path),
+            last_poll: Instant::now(),
+        }
+    }
+
+    // Returns true if the file has been written, replaced, created or removed since the last call
+    fn changed(&mut self) -> bool {
+        #[cfg(target_os = "linux")]
+        if let Some(inotify) = &mut self.inotify {
+            match inotify.changed() {
+                Some(changed) => return changed,
+                // The directory went away, so fall back to polling
+                None => self.inotify = None,
+            }
+        }
+
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+        let stamp = Stamp::of(&self.path);
+        if stamp == self.stamp {
+            return false;
+        }
+        self.stamp = stamp;
+        true
+    }
+}
+
+// What polling compares to tell whether a file has changed. The inode changes when an editor
+// replaces the file rather than writing it in place.
+#[derive(Debug, PartialEq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    dev: u64,
+    ino: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::ffi::{CString, OsString};
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    // Size of the fixed part of struct inotify_event, which is followed by the name
+    const EVENT_LEN: usize = 16;
+
+    // Watches the directory holding a file, since editors often save by writing a new file and
+    // renaming it over the old one, which a watch on the file itself would miss
+    pub struct Inotify {
+        file: File,
+        name: OsString,
+    }
+
+    impl Inotify {
+        // Returns None if the directory can't be watched, or the file is a symlink whose target
+        // may change somewhere else
+        pub fn new(path: &Path) -> Option<Self> {
+            let metadata = path.symlink_metadata();
+            if metadata.is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+                return None;
+            }
+            let name = path.file_name()?.to_os_string();
+            let dir = CString::new(path.parent()?.as_os_str().as_bytes()).ok()?;
+
+            // SAFETY: inotify_init1 has no preconditions, and a non-negative result is a new
+            // descriptor that nothing else owns
+            let file = unsafe {
+                let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+                if fd < 0 {
+                    return None;
+                }
+                File::from(OwnedFd::from_raw_fd(fd))
+            };
+            let mask = libc::IN_CLOSE_WRITE
+                | libc::IN_MOVED_TO
+                | libc::IN_MOVED_FROM
+                | libc::IN_CREATE
+                | libc::IN_DELETE;
+            // SAFETY: the descriptor is open and dir is NUL terminated
+            let wd = unsafe { libc::inotify_add_watch(file.as_raw_fd(), dir.as_ptr(), mask) };
+            (wd >= 0).then_some(Self { file, name })
+        }
+
+        // Reads the pending events, returning true if any were about the file, or None if the
+        // watch is gone because its directory was removed
+        pub fn changed(&mut self) -> Option<bool> {
+            let mut changed = false;
+            let mut buffer = [0; 4096];
+            loop {
+                let len = match self.file.read(&mut buffer) {
+                    Ok(0) => return Some(changed),
+                    Ok(len) => len,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Some(changed),
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return None,
+                };
+
+                let mut events = &buffer[..len];
+                while events.len() >= EVENT_LEN {
+                    let field =
+                        |i: usize| u32::from_ne_bytes(events[i * 4..i * 4 + 4].try_into().unwrap());
+                    let mask = field(1);
+                    let name_len = field(3) as usize;
+                    let name = events.get(EVENT_LEN..EVENT_LEN + name_len)?;
+                    let name = name.split(|&byte| byte == 0).next().unwrap_or_default();
+
+                    if mask & libc::IN_IGNORED != 0 {
+                        return None;
+                    }
+                    // Events were dropped, so any of them could have been about the file
+                    if mask & libc::IN_Q_OVERFLOW != 0 || name == self.name.as_bytes() {
+                        changed = true;
+                    }
+                    events = &events[EVENT_LEN + name_len..];
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::get_nickname;
+    use crate::tests::scratch_dir;
+    use std::env;
+
+    // Wraps a context that isn't loaded from a file, so it's never reloaded
+    pub(crate) fn live_context(ctx: AppContext) -> LiveContext {
+        LiveContext {
+            watcher: Watcher::new(Path::new("")),
+            config_flag: None,
+            ctx,
+        }
+    }
+
+    #[test]
+    fn test_watcher() {
+        let dir = scratch_dir("watcher");
+        let path = dir.join("config.toml");
+        fs::write(&path, "").unwrap();
+
+        let mut watchers = [Watcher::new(&path), Watcher::new(&path)];
+        // The second one always polls, and is made due for its next poll on each check
+        #[cfg(target_os = "linux")]
+        {
+            watchers[1].inotify = None;
+        }
+        let changed = |watchers: &mut [Watcher; 2]| {
+            watchers[1].last_poll = Instant::now() - POLL_INTERVAL;
+            [watchers[0].changed(), watchers[1].changed()]
+        };
+
+        assert_eq!(changed(&mut watchers), [false, false]);
+        fs::write(&path, "[git]\n").unwrap();
+        assert_eq!(changed(&mut watchers), [true, true]);
+        assert_eq!(changed(&mut watchers), [false, false]);
+
+        // Saving by renaming a new file over the old one
+        fs::write(dir.join("config.toml.tmp"), "[git]\nbranch = true\n").unwrap();
+        fs::rename(dir.join("config.toml.tmp"), &path).unwrap();
+        assert_eq!(changed(&mut watchers), [true, true]);
+
+        // Other files in the directory don't count
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        assert_eq!(changed(&mut watchers), [false, false]);
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(changed(&mut watchers), [true, true]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = scratch_dir("reload");
+        let path = dir.join("config.toml");
+        let project = dir.join("promptpath");
+        let config = |alias: &str| {
+            format!(
+                "projects = [{{ path = \"{}\", alias = \"{}\" }}]\n",
+                project.display(),
+                alias
+            )
+        };
+        fs::write(&path, config("first")).unwrap();
+
+        let mut live = LiveContext::new(Some(&path));
+        // Polling only notices changes once its interval has passed
+        let nickname = |live: &mut LiveContext| {
+            live.watcher.last_poll = Instant::now() - POLL_INTERVAL;
+            get_nickname(live.get(), project.join("src"))
+        };
+        assert_eq!(nickname(&mut live), "first/src");
+
+        fs::write(&path, config("second")).unwrap();
+        assert_eq!(nickname(&mut live), "second/src");
+
+        // A config that doesn't parse leaves the last good one in place
+        fs::write(&path, "projects = [\n").unwrap();
+        assert_eq!(nickname(&mut live), "second/src");
+
+        fs::write(&path, config("third")).unwrap();
+        assert_eq!(nickname(&mut live), "third/src");
+
+        // A relative path from the environment is found again from anywhere. No other test reads
+        // PROMPTPATH_CONFIG, as they all load a config given as the flag.
+        let cwd = env::current_dir().unwrap();
+        let mut relative: PathBuf = cwd.components().skip(1).map(|_| "..").collect();
+        relative.push(path.strip_prefix("/").unwrap());
+        env::set_var("PROMPTPATH_CONFIG", &relative);
+        let mut live = LiveContext::new(None);
+        env::remove_var("PROMPTPATH_CONFIG");
+        assert_eq!(live.config_flag, Some(cwd.join(&relative)));
+        assert_eq!(nickname(&mut live), "third/src");
+        fs::write(&path, config("fourth")).unwrap();
+        assert_eq!(nickname(&mut live), "fourth/src");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Nushell,
+    Elvish,
+}
+
+impl Shell {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            "nu" | "nushell" => Some(Shell::Nushell),
+            "elvish" => Some(Shell::Elvish),
+            _ => None,
+        }
+    }
+}
+
+// How text is escaped so it can be embedded in a shell's prompt string and still display literally
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escape {
+    // Control characters are made visible, nothing else changes. Suits prompts that print
+    // command output without expanding it again, like fish, nushell and elvish.
+    Plain,
+    // PS1 with promptvars: backslash escapes are decoded, then the result is expanded like a
+    // double quoted string
+    Bash,
+    // PROMPT with prompt_subst: the string is expanded like a double quoted string, then % escapes
+    // are processed
+    Zsh,
+    // fish_prompt prints its output verbatim, so this is the same as plain
+    Fish,
+}
+
+impl Escape {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(Escape::Plain),
+            "bash" => Some(Escape::Bash),
+            "zsh" => Some(Escape::Zsh),
+            "fish" => Some(Escape::Fish),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Escape::Plain => "plain",
+            Escape::Bash => "bash",
+            Escape::Zsh => "zsh",
+            Escape::Fish => "fish",
+        }
+    }
+
+    // Escapes text for embedding in a prompt. Control characters, which could move the cursor or
+    // change colors, are written out as \xNN.
+    pub fn escape(self, text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if c.is_control() {
+                for c in format!(r"\x{:02x}", c as u32).chars() {
+                    self.push_escaped(&mut escaped, c);
+                }
+            } else {
+                self.push_escaped(&mut escaped, c);
+            }
+        }
+        escaped
+    }
+
+    fn push_escaped(self, escaped: &mut String, c: char) {
+        match (self, c) {
+            // Decoding turns \\\\ into \\ and expansion turns that into \. A lone \$ would be
+            // decoded as the # or $ prompt character, so $ needs two backslashes too.
+            (Escape::Bash, '\\') => escaped.push_str(r"\\\\"),
+            (Escape::Bash, '$' | '`') => {
+                escaped.push_str(r"\\");
+                escaped.push(c);
+            }
+            (Escape::Zsh, '\\' | '$' | '`') => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            (Escape::Zsh, '%') => escaped.push_str("%%"),
+            _ => escaped.push(c),
+        }
+    }
+}
+
+// Placeholder in the init scripts for the command that runs promptpath. Its stderr is left
+// alone, so a broken config's warning, which is already throttled, still reaches the terminal.
+const COMMAND: &str = "@PROMPTPATH@";
+
+// Placeholder in the init scripts for the options promptpath is run with before each prompt
+const OPTIONS: &str = "@OPTIONS@";
+
+// Sets PROMPTPATH before each prompt, prepending to any existing PROMPT_COMMAND rather than
+// replacing it, and shows it wherever PS1 used \w
+const BASH_INIT: &str = r#"# promptpath integration for bash: eval "$(promptpath init bash)"
+_promptpath_hook() {
+    local status=$?
+    PROMPTPATH=$(@PROMPTPATH@ @OPTIONS@) || PROMPTPATH=$PWD
+    return $status
+}
+if [[ ${PROMPT_COMMAND[*]:-} != *_promptpath_hook* ]]; then
+    PROMPT_COMMAND="_promptpath_hook${PROMPT_COMMAND:+; $PROMPT_COMMAND}"
+fi
+PS1=${PS1//'\w'/'${PROMPTPATH}'}
+"#;
+
+// Sets PROMPTPATH from a precmd hook, which add-zsh-hook keeps alongside any others, and shows it
+// wherever PROMPT used %~. The value isn't expanded again but its % escapes would be processed, so
+// they're doubled where it's used.
+const ZSH_INIT: &str = r#"# promptpath integration for zsh: eval "$(promptpath init zsh)"
+_promptpath_precmd() {
+    PROMPTPATH=$(@PROMPTPATH@ @OPTIONS@) || PROMPTPATH=${(%):-%~}
+}
+autoload -Uz add-zsh-hook
+add-zsh-hook precmd _promptpath_precmd
+setopt prompt_subst
+PROMPT=${PROMPT//'%~'/'${PROMPTPATH//\%/%%}'}
+"#;
+
+// Sets PROMPTPATH as each prompt is about to be drawn and replaces prompt_pwd, which fish_prompt
+// and fish_title use to show the current directory, so any existing prompt picks it up
+const FISH_INIT: &str = r#"# promptpath integration for fish: promptpath init fish | source
+function _promptpath_update --on-event fish_prompt
+    set -g PROMPTPATH (@PROMPTPATH@ @OPTIONS@; or prompt_pwd_original)
+end
+if not functions -q prompt_pwd_original
+    functions -c prompt_pwd prompt_pwd_original
+end
+function prompt_pwd --description 'Print the current directory, shortened by promptpath'
+    if set -q PROMPTPATH[1]
+        echo $PROMPTPATH
+    else
+        prompt_pwd_original $argv
+    end
+end
+"#;
+
+// Replaces the left prompt closure, which by default shows the current directory
+const NUSHELL_INIT: &str = r#"# promptpath integration for nushell: add to config.nu
+#   promptpath init nushell | save -f ~/.cache/promptpath/init.nu
+#   source ~/.cache/promptpath/init.nu
+$env.PROMPT_COMMAND = {||
+    try { @PROMPTPATH@ @OPTIONS@ | str trim } catch { $env.PWD }
+}
+"#;
+
+// Replaces the prompt function, keeping elvish's default > suffix
+const ELVISH_INIT: &str = r#"# promptpath integration for elvish: eval (promptpath init elvish | slurp)
+set edit:prompt = {
+    try { put (@PROMPTPATH@ @OPTIONS@) } catch { put (tilde-abbr $pwd) }
+    put '> '
+}
+"#;
+
+// Starts the daemon in the background without job control noise. It exits right away if one is
+// already running. Nushell has no portable way to do this, so there it's left to the user.
+const POSIX_DAEMON_START: &str = "(@PROMPTPATH@ daemon </dev/null >/dev/null 2>&1 &)\n";
+const FISH_DAEMON_START: &str = "@PROMPTPATH@ daemon </dev/null &>/dev/null &\ndisown\n";
+const NUSHELL_DAEMON_START: &str = "# Start the daemon separately, e.g. with: promptpath daemon\n";
+const ELVISH_DAEMON_START: &str = "@PROMPTPATH@ daemon </dev/null >/dev/null 2>&1 &\n";
+
+// Returns the integration script for shell, running promptpath from exe. With daemon, the script
+// also starts a daemon and asks it for each prompt's path.
+pub fn init_script(shell: Shell, exe: &Path, daemon: bool) -> String {
+    let (template, daemon_start) = match shell {
+        Shell::Bash => (BASH_INIT, POSIX_DAEMON_START),
+        Shell::Zsh => (ZSH_INIT, POSIX_DAEMON_START),
+        Shell::Fish => (FISH_INIT, FISH_DAEMON_START),
+        Shell::Nushell => (NUSHELL_INIT, NUSHELL_DAEMON_START),
+        Shell::Elvish => (ELVISH_INIT, ELVISH_DAEMON_START),
+    };
+
+    let (options, daemon_start) = if daemon {
+        ("--daemon --shell plain", daemon_start)
+    } else {
+        ("--shell plain", "")
+    };
+    format!("{}{}", template, daemon_start)
+        .replace(OPTIONS, options)
+        .replace(COMMAND, &command(shell, exe))
+}
+
+// Quotes exe as a command for shell. Paths the shell can't quote fall back to looking promptpath
+// up in PATH.
+fn command(shell: Shell, exe: &Path) -> String {
+    let Some(exe) = exe.to_str() else {
+        return fallback_command(shell);
+    };
+
+    match shell {
+        Shell::Bash | Shell::Zsh => format!("command '{}'", exe.replace('\'', r"'\''")),
+        Shell::Fish => format!(
+            "command '{}'",
+            exe.replace('\\', r"\\").replace('\'', r"\'")
+        ),
+        Shell::Nushell if !exe.contains('\'') => format!("^'{}'", exe),
+        Shell::Nushell => fallback_command(shell),
+        Shell::Elvish => format!("(external '{}')", exe.replace('\'', "''")),
+    }
+}
+
+fn fallback_command(shell: Shell) -> String {
+    match shell {
+        Shell::Bash | Shell::Zsh | Shell::Fish => String::from("command promptpath"),
+        Shell::Nushell => String::from("^promptpath"),
+        Shell::Elvish => String::from("e:promptpath"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        let name = "%F{red}$(rm -rf ~)`id`\\x\x1b[31m\u{9b}";
+        let test_cases = [
+            (Escape::Plain, r"%F{red}$(rm -rf ~)`id`\x\x1b[31m\x9b"),
+            (Escape::Fish, r"%F{red}$(rm -rf ~)`id`\x\x1b[31m\x9b"),
+            (
+                Escape::Bash,
+                r"%F{red}\\$(rm -rf ~)\\`id\\`\\\\x\\\\x1b[31m\\\\x9b",
+            ),
+            (Escape::Zsh, r"%%F{red}\$(rm -rf ~)\`id\`\\x\\x1b[31m\\x9b"),
+        ];
+
+        for (escape, expected) in test_cases {
+            assert_eq!(escape.escape(name), expected, "Failed test: {:?}", escape);
+        }
+    }
+
+    #[test]
+    fn test_command() {
+        let test_cases = [
+            (
+                Shell::Bash,
+                "/usr/bin/promptpath",
+                "command '/usr/bin/promptpath'",
+            ),
+            (
+                Shell::Zsh,
+                "/opt/it's/promptpath",
+                r"command '/opt/it'\''s/promptpath'",
+            ),
+            (
+                Shell::Fish,
+                r"/opt/it's\/promptpath",
+                r"command '/opt/it\'s\\/promptpath'",
+            ),
+            (
+                Shell::Nushell,
+                "/usr/bin/promptpath",
+                "^'/usr/bin/promptpath'",
+            ),
+            (Shell::Nushell, "/opt/it's/promptpath", "^promptpath"),
+            (
+                Shell::Elvish,
+                "/opt/it's/promptpath",
+                "(external '/opt/it''s/promptpath')",
+            ),
+        ];
+
+        for (shell, exe, expected) in test_cases {
+            assert_eq!(
+                command(shell, Path::new(exe)),
+                expected,
+                "Failed test: {:?}",
+                shell
+            );
+        }
+    }
+
+    #[test]
+    fn test_init_script() {
+        for shell in [
+            Shell::Bash,
+            Shell::Zsh,
+            Shell::Fish,
+            Shell::Nushell,
+            Shell::Elvish,
+        ] {
+            let script = init_script(shell, Path::new("/usr/bin/promptpath"), false);
+            assert!(!script.contains(COMMAND), "Failed test: {:?}", shell);
+            assert!(!script.contains(OPTIONS), "Failed test: {:?}", shell);
+            assert!(!script.contains("daemon"), "Failed test: {:?}", shell);
+            assert!(
+                script.contains("/usr/bin/promptpath"),
+                "Failed test: {:?}",
+                shell
+            );
+
+            let script = init_script(shell, Path::new("/usr/bin/promptpath"), true);
+            assert!(!script.contains(COMMAND), "Failed test: {:?}", shell);
+            assert!(
+                script.contains("--daemon --shell plain"),
+                "Failed test: {:?}",
+                shell
+            );
+        }
+    }
+}
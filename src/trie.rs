@@ -0,0 +1,259 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+
+// Values keyed by absolute path, stored one path component per level, so the deepest key a path
+// starts with is found by walking down the path rather than by checking every key
+#[derive(Debug)]
+pub struct PathTrie<T> {
+    root: Node<T>,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    value: Option<T>,
+    children: HashMap<OsString, Node<T>, BuildHasherDefault<FnvHasher>>,
+}
+
+// 64-bit FNV-1a, which hashes short names like path components several times faster than the
+// standard library's hasher. Keys come from the user's own config, so there's no need for the
+// standard hasher's resistance to chosen collisions.
+struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl<T> Node<T> {
+    fn new() -> Self {
+        Self {
+            value: None,
+            children: HashMap::default(),
+        }
+    }
+
+    // Returns the child called name, adding it if there isn't one. The name is only copied for a
+    // new child, since most mappings share their first few components.
+    fn child(&mut self, name: &OsStr) -> &mut Self {
+        if !self.children.contains_key(name) {
+            self.children.insert(name.to_os_string(), Node::new());
+        }
+        self.children.get_mut(name).unwrap()
+    }
+}
+
+impl<T> PathTrie<T> {
+    pub fn new() -> Self {
+        Self {
+            root: Node::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Sets the value for path, returning the one it replaces
+    pub fn insert(&mut self, path: &Path, value: T) -> Option<T> {
+        let node = normal_components(path).fold(&mut self.root, Node::child);
+        let old = node.value.replace(value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    // Returns the value for the deepest key that path starts with, along with the number of
+    // components below the root in that key
+    pub fn longest_prefix(&self, path: &Path) -> Option<(usize, &T)> {
+        let mut node = &self.root;
+        let mut longest = node.value.as_ref().map(|value| (0, value));
+        for (depth, name) in normal_components(path).enumerate() {
+            let Some(child) = node.children.get(name) else {
+                break;
+            };
+            node = child;
+            if let Some(value) = &node.value {
+                longest = Some((depth + 1, value));
+            }
+        }
+        longest
+    }
+
+    // Moves every key starting with from to start with to instead, keeping the rest of the key.
+    // Where a key is already there, its value is kept and the moved one dropped.
+    pub fn move_prefix(&mut self, from: &Path, to: &Path) {
+        let Some(moved) = self.remove_subtree(from) else {
+            return;
+        };
+        let node = normal_components(to).fold(&mut self.root, Node::child);
+        self.len -= node.merge(moved);
+    }
+
+    // Takes out the node for path along with everything below it
+    fn remove_subtree(&mut self, path: &Path) -> Option<Node<T>> {
+        let names: Vec<&OsStr> = normal_components(path).collect();
+        let Some((last, parents)) = names.split_last() else {
+            return Some(mem::replace(&mut self.root, Node::new()));
+        };
+        let mut node = &mut self.root;
+        for name in parents {
+            node = node.children.get_mut(*name)?;
+        }
+        node.children.remove(*last)
+    }
+}
+
+impl<T> Node<T> {
+    // Adds other's values to this node and those below it, returning the number dropped because
+    // there was already a value for their key
+    fn merge(&mut self, other: Node<T>) -> usize {
+        let mut dropped = 0;
+        if let Some(value) = other.value {
+            if self.value.is_none() {
+                self.value = Some(value);
+            } else {
+                dropped += 1;
+            }
+        }
+        for (name, child) in other.children {
+            match self.children.entry(name) {
+                Entry::Occupied(entry) => dropped += entry.into_mut().merge(child),
+                Entry::Vacant(entry) => {
+                    entry.insert(child);
+                }
+            }
+        }
+        dropped
+    }
+}
+
+impl<T> Default for PathTrie<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> FromIterator<(PathBuf, T)> for PathTrie<T> {
+    fn from_iter<I: IntoIterator<Item = (PathBuf, T)>>(entries: I) -> Self {
+        let mut trie = Self::new();
+        for (path, value) in entries {
+            trie.insert(&path, value);
+        }
+        trie
+    }
+}
+
+impl<T, const N: usize> From<[(PathBuf, T); N]> for PathTrie<T> {
+    fn from(entries: [(PathBuf, T); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+// Keys are normalized absolute paths, so only their normal components tell them apart
+fn normal_components(path: &Path) -> impl Iterator<Item = &OsStr> {
+    path.components().filter_map(|component| match component {
+        Component::Normal(name) => Some(name),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_prefix() {
+        let trie = PathTrie::from([
+            (PathBuf::from("/Users/tcrypt/code"), "code"),
+            (PathBuf::from("/Users/tcrypt/code/promptpath"), "promptpath"),
+            (PathBuf::from("/Users/tcrypt/code/promptpath/vendor/x"), "x"),
+            (PathBuf::from("/opt"), "opt"),
+        ]);
+        assert_eq!(trie.len(), 4);
+
+        let test_cases = [
+            ("/Users/tcrypt/code", Some((3, "code"))),
+            ("/Users/tcrypt/code/other", Some((3, "code"))),
+            ("/Users/tcrypt/code/promptpath/src", Some((4, "promptpath"))),
+            (
+                "/Users/tcrypt/code/promptpath/vendor",
+                Some((4, "promptpath")),
+            ),
+            ("/Users/tcrypt/code/promptpath/vendor/x/y", Some((6, "x"))),
+            ("/Users/tcrypt/codes", None),
+            ("/Users/tcrypt", None),
+            ("/opt/", Some((1, "opt"))),
+            ("/", None),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(
+                trie.longest_prefix(Path::new(input))
+                    .map(|(depth, value)| (depth, *value)),
+                expected,
+                "Failed test: {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut trie = PathTrie::new();
+        assert_eq!(trie.insert(Path::new("/a/b"), 1), None);
+        assert_eq!(trie.insert(Path::new("/a/b/"), 2), Some(1));
+        assert_eq!(trie.insert(Path::new("/"), 3), None);
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.longest_prefix(Path::new("/c")), Some((0, &3)));
+    }
+
+    #[test]
+    fn test_move_prefix() {
+        let mut trie = PathTrie::from([
+            (PathBuf::from("/var/home/tcrypt/code"), "code"),
+            (PathBuf::from("/var/home/tcrypt/notes"), "moved notes"),
+            (PathBuf::from("/home/tcrypt/notes"), "notes"),
+            (PathBuf::from("/opt"), "opt"),
+        ]);
+        trie.move_prefix(Path::new("/var/home/tcrypt"), Path::new("/home/tcrypt"));
+        trie.move_prefix(Path::new("/srv"), Path::new("/home/tcrypt"));
+        assert_eq!(trie.len(), 3);
+
+        let test_cases = [
+            ("/home/tcrypt/code/x", Some((3, "code"))),
+            ("/home/tcrypt/notes", Some((3, "notes"))),
+            ("/var/home/tcrypt/code", None),
+            ("/opt/x", Some((1, "opt"))),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(
+                trie.longest_prefix(Path::new(input))
+                    .map(|(depth, value)| (depth, *value)),
+                expected,
+                "Failed test: {:?}",
+                input
+            );
+        }
+    }
+}